@@ -0,0 +1,311 @@
+//! Fitting and transforming Apache Arrow `RecordBatch`es, enabled with the `arrow` feature.
+//!
+//! Integer, boolean, string and dictionary-encoded columns are supported. Dictionary columns are aggregated and encoded
+//! by their integer codes, so each distinct value is hashed once per batch instead of once per row.
+//! Columns of other types, UInt64 values beyond the range of Int64 and batches with other columns than the fitted ones
+//! are reported as [`ArrowError`]s.
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::*;
+use arrow_array::{Array, RecordBatch};
+use arrow_schema::{ArrowError, DataType};
+use fnv::FnvHashMap;
+use ndarray::parallel::prelude::*;
+use ndarray::prelude::*;
+
+use crate::stats::TargetStats;
+use num_traits::Float;
+
+use crate::target_encoder::{hash_bytes, ColumnTargetEncoder, Data, DenseKey, Encoders, FrozenKey, MatrixEncoder, NumericKey};
+
+/// Category of an Arrow column. Nulls are a category of their own.
+/// Rows with the same string share it, so a column of keys holds a single copy of each distinct string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ArrowKey {
+    Null,
+    Int(i64),
+    Str(Arc<str>),
+}
+
+impl DenseKey for ArrowKey {
+    fn dense_code(&self) -> Option<usize> {
+        match self {
+            ArrowKey::Int(i) => i.dense_code(),
+            _ => None,
+        }
+    }
+}
+
+impl NumericKey for ArrowKey {
+    fn numeric_value<O: Float>(&self) -> Option<O> {
+        match self {
+            ArrowKey::Null => Some(O::nan()),
+            ArrowKey::Int(i) => O::from(*i),
+            ArrowKey::Str(_) => None,
+        }
+    }
+}
+
+/// Kind of [`ArrowKey`]s in model files
+pub(crate) const ARROW_KEYS: u64 = 5;
+
+impl FrozenKey for ArrowKey {
+    const KEY_KIND: u64 = ARROW_KEYS;
+
+    fn search_key(&self) -> u64 {
+        hash_bytes(&self.key_bytes().unwrap())
+    }
+
+    /// A tag byte for the variant followed by the value, so that the string "1" and the integer 1 are different keys
+    fn key_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        let bytes = match self {
+            ArrowKey::Null => vec![0],
+            ArrowKey::Int(i) => [&[1], &i.to_le_bytes()[..]].concat(),
+            ArrowKey::Str(s) => [&[2], s.as_bytes()].concat(),
+        };
+        Some(Cow::Owned(bytes))
+    }
+}
+
+/// Category at `row` of a non-dictionary `array`
+fn value_key(array: &dyn Array, row: usize) -> Result<ArrowKey, ArrowError> {
+    if array.is_null(row) {
+        return Ok(ArrowKey::Null);
+    }
+
+    let key = match array.data_type() {
+        DataType::Boolean => ArrowKey::Int(array.as_boolean().value(row) as i64),
+        DataType::Int8 => ArrowKey::Int(array.as_primitive::<Int8Type>().value(row) as i64),
+        DataType::Int16 => ArrowKey::Int(array.as_primitive::<Int16Type>().value(row) as i64),
+        DataType::Int32 => ArrowKey::Int(array.as_primitive::<Int32Type>().value(row) as i64),
+        DataType::Int64 => ArrowKey::Int(array.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => ArrowKey::Int(array.as_primitive::<UInt8Type>().value(row) as i64),
+        DataType::UInt16 => ArrowKey::Int(array.as_primitive::<UInt16Type>().value(row) as i64),
+        DataType::UInt32 => ArrowKey::Int(array.as_primitive::<UInt32Type>().value(row) as i64),
+        DataType::UInt64 => {
+            let value = array.as_primitive::<UInt64Type>().value(row);
+            let value = i64::try_from(value).map_err(|_| ArrowError::InvalidArgumentError(format!("UInt64 category {} does not fit into Int64", value)))?;
+            ArrowKey::Int(value)
+        },
+        DataType::Utf8 => ArrowKey::Str(Arc::from(array.as_string::<i32>().value(row))),
+        DataType::LargeUtf8 => ArrowKey::Str(Arc::from(array.as_string::<i64>().value(row))),
+        DataType::Utf8View => ArrowKey::Str(Arc::from(array.as_string_view().value(row))),
+        data_type => return Err(ArrowError::NotYetImplemented(format!("Arrow columns of type {} can not be used as categories", data_type))),
+    };
+    Ok(key)
+}
+
+/// String keys of `values`, with a single copy of each distinct string
+pub(crate) fn string_keys<'a>(values: impl Iterator<Item = Option<&'a str>>) -> Array1<ArrowKey> {
+    let mut interned: FnvHashMap<&str, ArrowKey> = FnvHashMap::default();
+    values.map(|value| match value {
+        Some(s) => interned.entry(s).or_insert_with(|| ArrowKey::Str(Arc::from(s))).clone(),
+        None => ArrowKey::Null,
+    }).collect()
+}
+
+/// Categories of all rows of `array`. Dictionary values are converted once and shared by the rows with their code.
+fn column_keys(array: &dyn Array) -> Result<Array1<ArrowKey>, ArrowError> {
+    if let Some(dictionary) = array.as_any_dictionary_opt() {
+        let values = dictionary.values();
+        let value_keys = (0..values.len()).map(|c| value_key(values, c)).collect::<Result<Vec<_>, _>>()?;
+        return Ok(dense_codes(array).iter().map(|code| code.map_or(ArrowKey::Null, |c| value_keys[c].clone())).collect());
+    }
+
+    match array.data_type() {
+        DataType::Utf8 => Ok(string_keys(array.as_string::<i32>().iter())),
+        DataType::LargeUtf8 => Ok(string_keys(array.as_string::<i64>().iter())),
+        DataType::Utf8View => Ok(string_keys(array.as_string_view().iter())),
+        _ => (0..array.len()).map(|row| value_key(array, row)).collect(),
+    }
+}
+
+/// Dictionary codes of a dictionary-encoded `array`, `None` for null rows
+fn dense_codes(array: &dyn Array) -> Vec<Option<usize>> {
+    let dictionary = array.as_any_dictionary();
+    if dictionary.values().is_empty() {
+        return vec![None; array.len()];
+    }
+
+    dictionary.normalized_keys()
+        .into_iter()
+        .enumerate()
+        .map(|(row, code)| if array.is_null(row) || dictionary.values().is_null(code) { None } else { Some(code) })
+        .collect()
+}
+
+/// Category statistics of a dictionary-encoded column, collected in a dense vector indexed by dictionary code
+fn dictionary_stats<D: Data, T: Data>(array: &dyn Array, target: &Array1<T>) -> Result<FnvHashMap<ArrowKey, TargetStats<D>>, ArrowError> {
+    let values = array.as_any_dictionary().values();
+    let mut code_stats: Vec<TargetStats<D>> = vec![TargetStats::new(); values.len()];
+    let mut null_stats = TargetStats::new();
+    for (code, t) in dense_codes(array).into_iter().zip(target.iter()) {
+        let t = D::from(*t).unwrap();
+        match code {
+            Some(code) => code_stats[code].push(t),
+            None => null_stats.push(t),
+        }
+    }
+
+    // a dictionary may contain the same value more than once
+    let mut stats: FnvHashMap<ArrowKey, TargetStats<D>> = FnvHashMap::default();
+    for (code, group_stats) in code_stats.iter().enumerate().filter(|(_, s)| s.count > 0) {
+        stats.entry(value_key(values, code)?).or_default().merge(group_stats);
+    }
+    if null_stats.count > 0 {
+        stats.entry(ArrowKey::Null).or_default().merge(&null_stats);
+    }
+    Ok(stats)
+}
+
+impl<D, T> MatrixEncoder<ArrowKey, D, T>
+where
+    T: Data,
+    D: Data,
+{
+    /// Fit an encoder on all columns of `batch`. Column names are taken from the batch schema.
+    pub fn fit_record_batch(batch: &RecordBatch, target: &Array1<T>, encoder: &mut Encoders<D>) -> Result<MatrixEncoder<ArrowKey, D, T>, ArrowError> {
+        assert_eq!(batch.num_rows(), target.len(), "Batch must have a target value for each row");
+        let target_stats: TargetStats<D> = target.iter().map(|t| D::from(*t).unwrap()).collect();
+        encoder.set_global_stats(&target_stats);
+
+        let encodings = batch.columns()
+            .par_iter()
+            .map(|column| {
+                if column.as_any_dictionary_opt().is_some() {
+                    Ok(ColumnTargetEncoder::from_stats(dictionary_stats(column.as_ref(), target)?, encoder))
+                } else {
+                    Ok(ColumnTargetEncoder::fit(&column_keys(column.as_ref())?, target, encoder))
+                }
+            })
+            .collect::<Result<_, ArrowError>>()?;
+
+        let names = batch.schema().fields().iter().map(|f| f.name().clone()).collect();
+        Ok(MatrixEncoder::from_columns(encoder.clone(), target_stats, encodings).with_column_names(names))
+    }
+
+    /// Encode all columns of `batch` into a new array.
+    /// The batch must have the same columns as the one the encoder was fitted on.
+    pub fn transform_record_batch(&self, batch: &RecordBatch) -> Result<Array2<T>, ArrowError> {
+        let names: Vec<String> = batch.schema().fields().iter().map(|f| f.name().clone()).collect();
+        self.check_column_names(&names).map_err(ArrowError::SchemaError)?;
+        if names.len() != self.columns().len() {
+            return Err(ArrowError::SchemaError(format!(
+                "Batch has {} columns, but the encoder was fitted on {} columns", names.len(), self.columns().len()
+            )));
+        }
+
+        let mut encoded = Array2::zeros((batch.num_rows(), batch.num_columns()));
+        encoded.axis_iter_mut(Axis(1))
+            .into_par_iter()
+            .zip(batch.columns().par_iter())
+            .zip(self.columns().par_iter())
+            .try_for_each(|((mut encoded_column, column), encoder)| {
+                match column.as_any_dictionary_opt() {
+                    Some(dictionary) => {
+                        // encode each dictionary value once, then look rows up by their codes
+                        let values = dictionary.values();
+                        let value_encodings = (0..values.len())
+                            .map(|c| Ok(encoder.encode(&value_key(values, c)?)))
+                            .collect::<Result<Vec<T>, ArrowError>>()?;
+                        let null_encoding = encoder.encode(&ArrowKey::Null);
+                        for (e, code) in encoded_column.iter_mut().zip(dense_codes(column.as_ref())) {
+                            *e = code.map_or(null_encoding, |c| value_encodings[c]);
+                        }
+                    },
+                    None => encoded_column.assign(&column_keys(column.as_ref())?.map(|k| encoder.encode(k))),
+                }
+                Ok::<_, ArrowError>(())
+            })?;
+        Ok(encoded)
+    }
+}
+
+/// Categories of all columns of `batch` as a 2d array, for the methods of [`MatrixEncoder`] that take `Array2`.
+/// Rows share the strings of their category, so each distinct string of a column is copied once.
+pub fn record_batch_keys(batch: &RecordBatch) -> Result<Array2<ArrowKey>, ArrowError> {
+    let mut keys = Array2::from_elem((batch.num_rows(), batch.num_columns()), ArrowKey::Null);
+    for (mut keys_column, column) in keys.axis_iter_mut(Axis(1)).zip(batch.columns()) {
+        keys_column.assign(&column_keys(column.as_ref())?);
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use arrow_array::{ArrayRef, DictionaryArray, Int64Array, StringArray};
+    use ndarray::array;
+
+    #[test]
+    fn test_record_batch_matches_array_fit() {
+        let cities: DictionaryArray<Int32Type> = vec![Some("a"), Some("b"), Some("a"), None, Some("b"), Some("a")].into_iter().collect();
+        let ids = Int64Array::from(vec![Some(7), Some(7), Some(3), Some(3), None, Some(7)]);
+        let names = StringArray::from(vec!["x", "y", "x", "y", "x", "z"]);
+        let batch = RecordBatch::try_from_iter(vec![
+            ("city", Arc::new(cities) as ArrayRef),
+            ("id", Arc::new(ids) as ArrayRef),
+            ("name", Arc::new(names) as ArrayRef),
+        ]).unwrap();
+        let target = array![0.4, 0.9, 0.1, 0.3, 0.8, 0.5];
+
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_record_batch(&batch, &target, &mut Encoders::new_target_encoder(1, 1.0)).unwrap();
+        let keys = record_batch_keys(&batch).unwrap();
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&keys, &target, &mut Encoders::new_target_encoder(1, 1.0));
+
+        assert_eq!(keys[[3, 0]], ArrowKey::Null);
+        assert_eq!(encoder.column_names(), Some(&["city".to_owned(), "id".to_owned(), "name".to_owned()][..]));
+        let encoded = encoder.transform_record_batch(&batch).unwrap();
+        let expected = expected.transform_to(keys.view());
+        for (a, b) in encoded.iter().zip(expected.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_dictionary_with_unseen_and_repeated_values() {
+        let fitted: DictionaryArray<Int8Type> = vec!["a", "b", "a", "a"].into_iter().collect();
+        let batch = RecordBatch::try_from_iter(vec![("c", Arc::new(fitted) as ArrayRef)]).unwrap();
+        let target = array![1.0, 0.0, 1.0, 0.0];
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_record_batch(&batch, &target, &mut Encoders::new_target_encoder(1, 1.0)).unwrap();
+
+        // a different dictionary with a repeated value and a value not seen during fit
+        let keys = arrow_array::Int8Array::from(vec![0, 1, 2, 3]);
+        let values = StringArray::from(vec!["b", "c", "a", "b"]);
+        let other = DictionaryArray::try_new(keys, Arc::new(values)).unwrap();
+        let other = RecordBatch::try_from_iter(vec![("c", Arc::new(other) as ArrayRef)]).unwrap();
+
+        let encoded = encoder.transform_record_batch(&other).unwrap();
+        let column = &encoder.columns()[0];
+        assert_eq!(encoded.column(0).to_vec(), vec![
+            column.encode(&ArrowKey::Str("b".into())),
+            0.5,
+            column.encode(&ArrowKey::Str("a".into())),
+            column.encode(&ArrowKey::Str("b".into())),
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_batches_are_errors() {
+        let ids = RecordBatch::try_from_iter(vec![("id", Arc::new(arrow_array::UInt64Array::from(vec![1, u64::MAX])) as ArrayRef)]).unwrap();
+        assert!(record_batch_keys(&ids).is_err());
+        let floats = RecordBatch::try_from_iter(vec![("x", Arc::new(arrow_array::Float64Array::from(vec![0.5])) as ArrayRef)]).unwrap();
+        assert!(MatrixEncoder::<_, f64, f64>::fit_record_batch(&floats, &array![1.0], &mut Encoders::new_target_encoder(1, 1.0)).is_err());
+
+        let names = StringArray::from(vec!["x", "y", "x"]);
+        let batch = RecordBatch::try_from_iter(vec![("name", Arc::new(names.clone()) as ArrayRef)]).unwrap();
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_record_batch(&batch, &array![1.0, 0.0, 1.0], &mut Encoders::new_target_encoder(1, 1.0)).unwrap();
+        let renamed = RecordBatch::try_from_iter(vec![("other", Arc::new(names) as ArrayRef)]).unwrap();
+        assert!(matches!(encoder.transform_record_batch(&renamed), Err(ArrowError::SchemaError(_))));
+
+        let keys = record_batch_keys(&batch).unwrap();
+        match (&keys[[0, 0]], &keys[[2, 0]]) {
+            (ArrowKey::Str(a), ArrowKey::Str(b)) => assert!(Arc::ptr_eq(a, b)),
+            _ => panic!("Expected string keys"),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Assignment of rows to folds for out-of-fold (cross-fitted) encodings.
+//! See [`MatrixEncoder::fit_transform_oof`](crate::target_encoder::MatrixEncoder::fit_transform_oof).
+use std::hash::Hash;
+
+use fnv::FnvHashMap;
+use ndarray::Array1;
+use num_traits::Float;
+use ordered_float::OrderedFloat;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+/// Splits rows into `n_folds` folds. All splits are shuffled with `seed`, so the same seed always gives the same folds.
+#[derive(Debug, Clone, Copy)]
+pub struct FoldSplitter {
+    n_folds: usize,
+    seed: u64,
+}
+
+impl FoldSplitter {
+    pub fn new(n_folds: usize, seed: u64) -> FoldSplitter {
+        assert!(n_folds >= 2, "At least two folds are required for out-of-fold encoding");
+        FoldSplitter { n_folds, seed }
+    }
+
+    pub fn n_folds(&self) -> usize {
+        self.n_folds
+    }
+
+    /// Assign `n_rows` rows to folds of (almost) equal size at random
+    pub fn kfold(&self, n_rows: usize) -> Array1<usize> {
+        let mut rows: Vec<usize> = (0..n_rows).collect();
+        rows.shuffle(&mut self.rng());
+
+        let mut folds = Array1::zeros(n_rows);
+        for (position, &row) in rows.iter().enumerate() {
+            folds[row] = position * self.n_folds / n_rows;
+        }
+        folds
+    }
+
+    /// Assign rows to folds so that all rows of a group end up in the same fold.
+    /// Groups are distributed from the largest to the smallest, each one going to the fold with the fewest rows.
+    pub fn group_kfold<G: Hash + Eq + Clone>(&self, groups: &Array1<G>) -> Array1<usize> {
+        // unique groups in order of appearance, so that the split does not depend on hash map ordering
+        let mut group_index: FnvHashMap<G, usize> = FnvHashMap::default();
+        let mut group_sizes: Vec<usize> = Vec::new();
+        let row_groups: Vec<usize> = groups.iter()
+            .map(|g| {
+                let index = *group_index.entry(g.clone()).or_insert_with(|| {
+                    group_sizes.push(0);
+                    group_sizes.len() - 1
+                });
+                group_sizes[index] += 1;
+                index
+            })
+            .collect();
+        assert!(group_sizes.len() >= self.n_folds, "Number of groups must be at least the number of folds");
+
+        // shuffle first so that groups of equal size are assigned at random
+        let mut order: Vec<usize> = (0..group_sizes.len()).collect();
+        order.shuffle(&mut self.rng());
+        order.sort_by_key(|&g| std::cmp::Reverse(group_sizes[g]));
+
+        let mut fold_sizes = vec![0; self.n_folds];
+        let mut group_folds = vec![0; group_sizes.len()];
+        for g in order {
+            let fold = (0..self.n_folds).min_by_key(|&f| fold_sizes[f]).unwrap();
+            fold_sizes[fold] += group_sizes[g];
+            group_folds[g] = fold;
+        }
+
+        row_groups.iter().map(|&g| group_folds[g]).collect()
+    }
+
+    /// Assign rows to folds so that each class of a classification `target` is equally represented in every fold
+    pub fn stratified_kfold<T: Float>(&self, target: &Array1<T>) -> Array1<usize> {
+        let mut classes: Vec<(OrderedFloat<T>, usize)> = target.iter()
+            .enumerate()
+            .map(|(row, &t)| (OrderedFloat(t), row))
+            .collect();
+        classes.sort();
+
+        let mut rng = self.rng();
+        let mut folds = Array1::zeros(target.len());
+        let mut next_fold = 0;
+        for class in classes.chunk_by_mut(|a, b| a.0 == b.0) {
+            class.shuffle(&mut rng);
+            // continue from the fold where the previous class stopped to keep fold sizes balanced
+            for (_, row) in class.iter() {
+                folds[*row] = next_fold;
+                next_fold = (next_fold + 1) % self.n_folds;
+            }
+        }
+        folds
+    }
+
+    fn rng(&self) -> StdRng {
+        StdRng::seed_from_u64(self.seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_kfold_is_balanced_and_reproducible() {
+        let folds = FoldSplitter::new(3, 42).kfold(10);
+        let mut sizes = vec![0; 3];
+        folds.iter().for_each(|&f| sizes[f] += 1);
+        sizes.sort();
+        assert_eq!(sizes, vec![3, 3, 4]);
+        assert_eq!(folds, FoldSplitter::new(3, 42).kfold(10));
+    }
+
+    #[test]
+    fn test_group_kfold_keeps_groups_together() {
+        let groups = array![1, 1, 2, 3, 3, 3, 4, 5, 5, 6];
+        let folds = FoldSplitter::new(3, 7).group_kfold(&groups);
+        for (i, gi) in groups.iter().enumerate() {
+            for (j, gj) in groups.iter().enumerate() {
+                if gi == gj {
+                    assert_eq!(folds[i], folds[j]);
+                }
+            }
+        }
+        assert!((0..3).all(|f| folds.iter().any(|&x| x == f)));
+    }
+
+    #[test]
+    fn test_stratified_kfold() {
+        let target = array![0., 0., 0., 1., 1., 1., 0., 1., 0., 1., 0., 1.];
+        let folds = FoldSplitter::new(3, 1).stratified_kfold(&target);
+        for fold in 0..3 {
+            let positives = target.iter().zip(folds.iter()).filter(|(&t, &f)| f == fold && t == 1.).count();
+            let negatives = target.iter().zip(folds.iter()).filter(|(&t, &f)| f == fold && t == 0.).count();
+            assert_eq!((positives, negatives), (2, 2));
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Read-optimised encoders for scoring, see [`MatrixEncoder::freeze`](crate::target_encoder::MatrixEncoder::freeze).
+//!
+//! A frozen column stores its encodings in flat arrays sorted by the [`search_key`](crate::target_encoder::FrozenKey::search_key) of the
+//! categories and searched with a branchless binary search. Variable-length categories such as strings also store
+//! their bytes, so that categories with the same search key are told apart. The arrays only hold fixed-width values,
+//! and take less memory than a hash map, which keeps spare capacity and owns a copy of each category.
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use bytemuck::Pod;
+use fnv::FnvHashMap;
+use itertools::Itertools;
+use ndarray::prelude::*;
+use ndarray::Zip;
+use memmap2::Mmap;
+use rayon::prelude::*;
+
+use crate::target_encoder::{output_columns, passthrough_value, Data, Encoders, FrozenKey, Key, NumericKey, OutputColumn, Remainder};
+
+/// Encoder parameters and global statistics as `f64`, the type they are kept in by frozen encoders and model files
+pub(crate) fn params_f64<D: Data>(params: &Encoders<D>) -> Encoders<f64> {
+    let to_f64 = |x: D| x.to_f64().unwrap();
+    match *params {
+        Encoders::TargetEncoder { min_samples_leaf, smoothing, prior } => {
+            Encoders::TargetEncoder { min_samples_leaf, smoothing: to_f64(smoothing), prior: prior.map(to_f64) }
+        }
+        Encoders::JamesSteinEncoder { prior, global_target_var, global_target_count } => {
+            Encoders::JamesSteinEncoder { prior: prior.map(to_f64), global_target_var: global_target_var.map(to_f64), global_target_count }
+        }
+        Encoders::AutoTargetEncoder { prior, global_target_var } => {
+            Encoders::AutoTargetEncoder { prior: prior.map(to_f64), global_target_var: global_target_var.map(to_f64) }
+        }
+        Encoders::CountEncoder { prior } => Encoders::CountEncoder { prior: prior.map(to_f64) },
+    }
+}
+
+/// Read-only array of a frozen column, owned or in a memory-mapped model file, see [`model_file`](crate::model_file)
+pub(crate) enum Buffer<T> {
+    Owned(Vec<T>),
+    /// `len` values starting at byte `start` of the file, which is aligned for `T`
+    Mapped { map: Arc<Mmap>, start: usize, len: usize },
+}
+
+impl<T: Pod> Deref for Buffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Buffer::Owned(values) => values,
+            Buffer::Mapped { map, start, len } => bytemuck::cast_slice(&map[*start..*start + *len * size_of::<T>()]),
+        }
+    }
+}
+
+/// Encodings of a single column in flat arrays sorted by search key
+pub struct FrozenColumn<K, T> {
+    /// Sorted search keys of the categories
+    pub(crate) search_keys: Buffer<u64>,
+    /// Encodings of the categories, in the order of `search_keys`
+    pub(crate) encodings: Buffer<T>,
+    /// Bytes of category `i` are `bytes[offsets[i]..offsets[i + 1]]`. Both are empty if search keys identify the categories.
+    pub(crate) offsets: Buffer<u64>,
+    pub(crate) bytes: Buffer<u8>,
+    pub(crate) unknown: T,
+    /// Parameters and global statistics the encodings were computed with
+    pub(crate) params: Encoders<f64>,
+    pub(crate) phantom_key: PhantomData<K>,
+}
+
+/// Index of the first of the sorted `keys` that is not smaller than `key`, without unpredictable branches
+fn lower_bound(keys: &[u64], key: u64) -> usize {
+    if keys.is_empty() {
+        return 0;
+    }
+    let mut base = 0;
+    let mut size = keys.len();
+    while size > 1 {
+        let half = size / 2;
+        let mid = base + half;
+        base = if keys[mid] < key { mid } else { base };
+        size -= half;
+    }
+    base + (keys[base] < key) as usize
+}
+
+impl<K: Key + FrozenKey, T: Data + Pod> FrozenColumn<K, T> {
+    /// Freeze the encodings of the categories of a column. Categories that are not in `encodings` are encoded with `unknown`.
+    pub(crate) fn new(encodings: &FnvHashMap<K, T>, unknown: T, params: Encoders<f64>) -> FrozenColumn<K, T> {
+        let mut entries = encodings.iter()
+            .map(|(k, &encoding)| (k.search_key(), k.key_bytes(), encoding))
+            .collect_vec();
+        entries.par_sort_unstable_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+        let mut offsets = Vec::new();
+        let mut bytes = Vec::new();
+        if entries.iter().any(|(_, key_bytes, _)| key_bytes.is_some()) {
+            offsets.reserve_exact(entries.len() + 1);
+            offsets.push(0);
+            for (_, key_bytes, _) in &entries {
+                bytes.extend_from_slice(key_bytes.as_deref().unwrap_or(&[]));
+                offsets.push(bytes.len() as u64);
+            }
+        }
+
+        FrozenColumn {
+            search_keys: Buffer::Owned(entries.iter().map(|(search_key, _, _)| *search_key).collect()),
+            encodings: Buffer::Owned(entries.iter().map(|(_, _, encoding)| *encoding).collect()),
+            offsets: Buffer::Owned(offsets),
+            bytes: Buffer::Owned(bytes),
+            unknown,
+            params,
+            phantom_key: PhantomData,
+        }
+    }
+
+    /// Number of categories with an encoding
+    pub fn len(&self) -> usize {
+        self.search_keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.search_keys.is_empty()
+    }
+
+    /// Encoding of categories that were not seen during fit
+    pub fn unknown(&self) -> T {
+        self.unknown
+    }
+
+    /// Parameters and global statistics the encodings of this column were computed with
+    pub fn params(&self) -> &Encoders<f64> {
+        &self.params
+    }
+
+    /// Number of bytes used by the arrays of this column, in memory or in the mapped model file
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>()
+            + self.search_keys.len() * size_of::<u64>()
+            + self.encodings.len() * size_of::<T>()
+            + self.offsets.len() * size_of::<u64>()
+            + self.bytes.len()
+    }
+
+    /// Encoding of a single category, the encoding of unknown categories if it was not seen during fit
+    pub fn encode(&self, key: &K) -> T {
+        let search_key = key.search_key();
+        let mut i = lower_bound(&self.search_keys, search_key);
+        if self.offsets.is_empty() {
+            return match self.search_keys.get(i) {
+                Some(&k) if k == search_key => self.encodings[i],
+                _ => self.unknown,
+            };
+        }
+
+        let key_bytes = key.key_bytes();
+        let key_bytes = key_bytes.as_deref().unwrap_or(&[]);
+        while i < self.search_keys.len() && self.search_keys[i] == search_key {
+            // offsets of a mapped file are not validated when it is opened, corrupt ones don't match any key
+            if self.bytes.get(self.offsets[i] as usize..self.offsets[i + 1] as usize) == Some(key_bytes) {
+                return self.encodings[i];
+            }
+            i += 1;
+        }
+        self.unknown
+    }
+
+    /// Encode provided `data` into a new array
+    pub fn transform_to(&self, data: ArrayView1<K>) -> Array1<T> {
+        data.map(|k| self.encode(k))
+    }
+}
+
+/// Read-only [`MatrixEncoder`](crate::target_encoder::MatrixEncoder) for scoring, with a [`FrozenColumn`] for each encoded column
+pub struct FrozenEncoder<K, T> {
+    /// Default parameters and global statistics of the encoder this one was frozen from
+    pub(crate) params: Encoders<f64>,
+    pub(crate) column_names: Option<Vec<String>>,
+    pub(crate) input_columns: Vec<usize>,
+    pub(crate) num_input_columns: usize,
+    pub(crate) remainder: Remainder,
+    pub(crate) columns: Vec<FrozenColumn<K, T>>,
+}
+
+impl<K: Key + FrozenKey, T: Data + Pod> FrozenEncoder<K, T> {
+    pub(crate) fn new(
+        params: Encoders<f64>,
+        column_names: Option<Vec<String>>,
+        input_columns: Vec<usize>,
+        num_input_columns: usize,
+        remainder: Remainder,
+        columns: Vec<FrozenColumn<K, T>>,
+    ) -> FrozenEncoder<K, T> {
+        FrozenEncoder { params, column_names, input_columns, num_input_columns, remainder, columns }
+    }
+
+    /// Default parameters and global statistics of the encoder this one was frozen from.
+    /// Parameters of each column are available from [`columns`](FrozenEncoder::columns).
+    pub fn params(&self) -> &Encoders<f64> {
+        &self.params
+    }
+
+    /// Frozen encoders of the encoded columns
+    pub fn columns(&self) -> &[FrozenColumn<K, T>] {
+        &self.columns
+    }
+
+    /// Indices of the input columns encoded by each of [`columns`](FrozenEncoder::columns)
+    pub fn input_columns(&self) -> &[usize] {
+        &self.input_columns
+    }
+
+    /// Names of the encoded columns, if the encoder had them
+    pub fn column_names(&self) -> Option<&[String]> {
+        self.column_names.as_deref()
+    }
+
+    /// Number of columns of the output of [`transform_to`](FrozenEncoder::transform_to)
+    pub fn num_output_columns(&self) -> usize {
+        match self.remainder {
+            Remainder::Passthrough => self.num_input_columns,
+            Remainder::Drop => self.columns.len(),
+        }
+    }
+
+    /// Number of bytes used by the arrays of all columns
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>() + self.columns.iter().map(|column| column.memory_size()).sum::<usize>()
+    }
+
+    /// Encode provided `data` into a new array, the same as `transform_to` of the encoder this one was frozen from
+    pub fn transform_to(&self, data: ArrayView2<K>) -> Array2<T>
+    where
+        K: NumericKey,
+    {
+        let mut encoded = Array2::zeros((data.len_of(Axis(0)), self.num_output_columns()));
+        self.transform_into(data, encoded.view_mut());
+        encoded
+    }
+
+    /// Encode provided `data` into the preallocated array `encoded`, in parallel over rows
+    pub fn transform_into(&self, data: ArrayView2<K>, mut encoded: ArrayViewMut2<T>)
+    where
+        K: NumericKey,
+    {
+        assert_eq!(data.len_of(Axis(1)), self.num_input_columns, "Data must have the same number of columns as the fitted data");
+        assert_eq!(encoded.dim(), (data.len_of(Axis(0)), self.num_output_columns()), "Output must have a row for each row of the data and a column for each output column");
+        let output_columns = output_columns(&self.input_columns, self.num_input_columns, self.remainder);
+
+        Zip::from(encoded.rows_mut())
+            .and(data.rows())
+            .par_for_each(|mut encoded_row, row| {
+                for (e, &column) in encoded_row.iter_mut().zip(output_columns.iter()) {
+                    *e = match column {
+                        OutputColumn::Encoded(j) => self.columns[j].encode(&row[self.input_columns[j]]),
+                        OutputColumn::Passthrough(i) => passthrough_value(&row[i]),
+                    };
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+    use ordered_float::OrderedFloat;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    use crate::target_encoder::{Encoders, MatrixEncoder};
+
+    #[test]
+    fn test_lower_bound() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for len in 0..40 {
+            let mut keys: Vec<u64> = (0..len).map(|_| rng.gen_range(0..20)).collect();
+            keys.sort_unstable();
+            for key in 0..22 {
+                assert_eq!(lower_bound(&keys, key), keys.partition_point(|&k| k < key));
+            }
+        }
+    }
+
+    #[test]
+    fn test_frozen_matches_encoder() {
+        let data = array![["a", "x", "1"], ["b", "x", "2"], ["a", "y", "3"], ["c", "y", "4"], ["a", "x", "5"]];
+        let target = array![0.1, 0.9, 0.4, 0.6, 0.2];
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_columns(&data, &target, &[0, 1], &mut Encoders::new_target_encoder(1, 1.0))
+            .with_remainder(Remainder::Drop);
+        let frozen = encoder.freeze();
+
+        let unseen = array![["a", "x", "1"], ["d", "", "2"], ["", "y", "3"]];
+        assert_eq!(frozen.transform_to(data.view()), encoder.transform_to(data.view()));
+        assert_eq!(frozen.transform_to(unseen.view()), encoder.transform_to(unseen.view()));
+        assert_eq!(frozen.columns()[0].len(), 3);
+        assert_eq!(frozen.columns()[0].unknown(), encoder.params().prior().unwrap());
+
+        let floats = data.mapv(|k| OrderedFloat(k.len() as f64 * if k == "x" { -0. } else { 1. }));
+        let float_encoder = MatrixEncoder::<_, f64, f32>::fit(&floats, &target.mapv(|t| t as f32), &mut Encoders::new_target_encoder(1, 1.0));
+        let query = array![[OrderedFloat(1.), OrderedFloat(0.), OrderedFloat(f64::NAN)]];
+        assert_eq!(float_encoder.freeze().transform_to(query.view()), float_encoder.transform_to(query.view()));
+    }
+
+    #[test]
+    fn test_frozen_missing_strings() {
+        let data = array![[None, Some(Arc::from("None"))], [Some(Arc::from("None")), None], [None, Some(Arc::from(""))]];
+        let target = array![1.0, 0.0, 1.0];
+        let encoder = MatrixEncoder::<Option<Arc<str>>, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 0.0));
+        let frozen = encoder.freeze();
+
+        assert_eq!(frozen.columns()[0].len(), 2);
+        assert_eq!(frozen.transform_to(data.view()), encoder.transform_to(data.view()));
+        assert_ne!(frozen.columns()[0].encode(&None), frozen.columns()[0].encode(&Some(Arc::from("None"))));
+    }
+
+    #[test]
+    fn test_frozen_is_smaller() {
+        let ids: Array2<String> = Array2::from_shape_fn((10_000, 1), |(i, _)| format!("user-{}", i));
+        let target = Array1::from_shape_fn(10_000, |i| (i % 7) as f64);
+        let encoder = MatrixEncoder::<_, f64, f64>::fit(&ids, &target, &mut Encoders::new_target_encoder(1, 1.0));
+        let frozen = encoder.freeze();
+
+        assert!(frozen.memory_size() < encoder.memory_size() / 2);
+        assert_eq!(frozen.transform_to(ids.view()), encoder.transform_to(ids.view()));
+    }
+}
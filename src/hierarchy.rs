@@ -0,0 +1,265 @@
+//! Target encoding of nested categories such as `country > region > city`, see [`HierarchicalEncoder`].
+//!
+//! Each level is keyed by the path of categories from the top of the hierarchy, so that cities with the same name in
+//! different regions are different categories. Encodings of a level use the encoding of the parent category as the prior
+//! instead of the global prior, and categories not seen during fit back off to their nearest seen ancestor.
+use fnv::FnvHashMap;
+use itertools::Itertools;
+use ndarray::parallel::prelude::*;
+use ndarray::prelude::*;
+use ndarray::Zip;
+use num_traits::{Float, FromPrimitive};
+
+use crate::interactions::{CompositeKey, Dictionary};
+use crate::stats::TargetStats;
+use crate::target_encoder::{compute_encoding, Data, Encoders, Key};
+
+/// Target encoding of a hierarchy of columns, ordered from the top level down to the leaves
+pub struct HierarchicalEncoder<K, D, T>
+where
+    D: Float + FromPrimitive,
+    T: Float,
+{
+    params: Encoders<D>,
+    target_stats: TargetStats<D>,
+    levels: Vec<usize>,
+    min_count: usize,
+    dictionaries: Vec<Dictionary<K>>,
+    /// Statistics of each level by the codes of the path to the category
+    stats: Vec<FnvHashMap<CompositeKey, TargetStats<D>>>,
+    /// Encodings of each level by the codes of the path to the category, after back-off
+    encodings: Vec<FnvHashMap<CompositeKey, T>>,
+    /// Encoding of paths whose top category was not seen during fit
+    unknown: T,
+}
+
+impl<K, D, T> HierarchicalEncoder<K, D, T>
+where
+    K: Key,
+    D: Data,
+    T: Data,
+{
+    /// Fit encodings of the input columns `levels` of `data`, ordered from the top of the hierarchy down to the leaves.
+    /// The top level is encoded with the global prior of `encoder`, every other level with the encoding of its parent category as the prior.
+    pub fn fit<S: ndarray::Data<Elem = K>>(
+        data: &ArrayBase<S, Ix2>,
+        target: &Array1<T>,
+        levels: &[usize],
+        encoder: &mut Encoders<D>,
+    ) -> HierarchicalEncoder<K, D, T> {
+        assert!(levels.len() >= 2, "A hierarchy must have at least two levels");
+        assert!(levels.iter().all(|&i| i < data.len_of(Axis(1))), "Column indices must be smaller than the number of columns");
+        assert!(levels.iter().all_unique(), "Levels of a hierarchy must be unique");
+
+        let target_stats: TargetStats<D> = target.iter().map(|t| D::from(*t).unwrap()).collect();
+        encoder.set_global_stats(&target_stats);
+        let mut hierarchy = HierarchicalEncoder {
+            params: encoder.clone(),
+            target_stats,
+            levels: levels.to_vec(),
+            min_count: 1,
+            dictionaries: vec![Dictionary::new(); levels.len()],
+            stats: vec![FnvHashMap::default(); levels.len()],
+            encodings: Vec::new(),
+            unknown: T::zero(),
+        };
+        hierarchy.update(data.view(), target);
+        hierarchy
+    }
+
+    /// Update the encoder with a new batch of rows without going back to the data seen before
+    pub fn partial_fit<S: ndarray::Data<Elem = K>>(&mut self, data: &ArrayBase<S, Ix2>, target: &Array1<T>) {
+        let batch_stats: TargetStats<D> = target.iter().map(|t| D::from(*t).unwrap()).collect();
+        self.target_stats.merge(&batch_stats);
+        self.params.set_global_stats(&self.target_stats);
+        self.update(data.view(), target);
+    }
+
+    fn update(&mut self, data: ArrayView2<K>, target: &Array1<T>) {
+        let codes: Vec<Vec<u32>> = self.dictionaries
+            .par_iter_mut()
+            .zip(self.levels.par_iter())
+            .map(|(dictionary, &i)| data.column(i).iter().map(|k| dictionary.insert(k)).collect())
+            .collect();
+
+        self.stats.par_iter_mut().enumerate().for_each(|(level, stats)| {
+            for (row, t) in target.iter().enumerate() {
+                let key: CompositeKey = codes[..=level].iter().map(|level_codes| level_codes[row]).collect();
+                stats.entry(key).or_default().push(D::from(*t).unwrap());
+            }
+        });
+        self.compute_encodings();
+    }
+
+    /// Compute encodings level by level, so that the encodings of the parents are known
+    fn compute_encodings(&mut self) {
+        let global_prior = self.params.prior().expect("Target statistics were not computed prior to encoding computation");
+        let mut encodings: Vec<FnvHashMap<CompositeKey, D>> = Vec::with_capacity(self.levels.len());
+        for stats in self.stats.iter() {
+            let num_groups = stats.values().filter(|s| s.count >= self.min_count).count();
+            let parents = encodings.last();
+            let level_encodings = stats.par_iter()
+                .map(|(key, group_stats)| {
+                    let prior = parents.map_or(global_prior, |parents| parents[&key[..key.len() - 1]]);
+                    if group_stats.count < self.min_count {
+                        return (key.clone(), prior);
+                    }
+                    let encoding = compute_encoding(&self.params.with_prior(prior), group_stats, num_groups)
+                        .expect("Error while computing encoding in a hierarchy");
+                    (key.clone(), encoding)
+                })
+                .collect();
+            encodings.push(level_encodings);
+        }
+        // encodings are computed in the statistics type, as they are the priors of the next level, and stored as `T`
+        self.encodings = encodings.into_iter()
+            .map(|level_encodings| level_encodings.into_iter().map(|(key, encoding)| (key, T::from(encoding).unwrap())).collect())
+            .collect();
+        self.unknown = T::from(global_prior).unwrap();
+    }
+
+    /// Categories with fewer than `min_count` rows are encoded as their parent, as if they were not seen during fit
+    pub fn with_min_count(mut self, min_count: usize) -> HierarchicalEncoder<K, D, T> {
+        self.min_count = min_count;
+        self.compute_encodings();
+        self
+    }
+
+    /// Recompute encodings with new encoder parameters from the stored category statistics
+    pub fn with_params(&self, encoder: &Encoders<D>) -> HierarchicalEncoder<K, D, T> {
+        let mut params = encoder.clone();
+        params.set_global_stats(&self.target_stats);
+        let mut hierarchy = HierarchicalEncoder {
+            params,
+            target_stats: self.target_stats,
+            levels: self.levels.clone(),
+            min_count: self.min_count,
+            dictionaries: self.dictionaries.clone(),
+            stats: self.stats.clone(),
+            encodings: Vec::new(),
+            unknown: T::zero(),
+        };
+        hierarchy.compute_encodings();
+        hierarchy
+    }
+
+    /// Input columns of the levels, from the top of the hierarchy down to the leaves
+    pub fn levels(&self) -> &[usize] {
+        &self.levels
+    }
+
+    /// Encode each level of `path`, falling back to the encoding of the nearest seen ancestor for unseen categories
+    fn encode_path<'a>(&self, path: impl Iterator<Item = &'a K>, mut encoded: ArrayViewMut1<T>)
+    where
+        K: 'a,
+    {
+        let mut encoding = self.unknown;
+        let mut key = Some(CompositeKey::new());
+        for ((level, k), e) in path.enumerate().zip(encoded.iter_mut()) {
+            key = key
+                .and_then(|mut key| {
+                    key.push(*self.dictionaries[level].codes.get(k)?);
+                    Some(key)
+                })
+                .filter(|key| self.encodings[level].contains_key(key));
+            if let Some(key) = &key {
+                encoding = self.encodings[level][key];
+            }
+            *e = encoding;
+        }
+    }
+
+    /// Encoding of the last category of `path`, which starts at the top of the hierarchy
+    pub fn encode(&self, path: &[K]) -> T {
+        assert!(!path.is_empty() && path.len() <= self.levels.len(), "Path must have between one and {} categories", self.levels.len());
+        let mut encoded = Array1::zeros(path.len());
+        self.encode_path(path.iter(), encoded.view_mut());
+        encoded[path.len() - 1]
+    }
+
+    /// Encode the levels of `data` into a new array with a column per level
+    pub fn transform_to(&self, data: ArrayView2<K>) -> Array2<T> {
+        let mut encoded = Array2::zeros((data.len_of(Axis(0)), self.levels.len()));
+        Zip::from(encoded.rows_mut())
+            .and(data.rows())
+            .par_for_each(|encoded_row, row| self.encode_path(self.levels.iter().map(|&i| &row[i]), encoded_row));
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+    use crate::target_encoder::ColumnTargetEncoder;
+
+    fn geo() -> (Array2<&'static str>, Array1<f64>) {
+        let data = array![
+            ["fr", "idf", "paris"], ["fr", "idf", "paris"], ["fr", "idf", "versailles"], ["fr", "paca", "nice"],
+            ["fr", "paca", "nice"], ["us", "tx", "paris"], ["us", "tx", "austin"], ["us", "tx", "austin"],
+        ];
+        (data, array![0.9, 0.7, 0.8, 0.3, 0.5, 0.1, 0.2, 0.4])
+    }
+
+    #[test]
+    fn test_levels_use_parent_prior() {
+        let (data, target) = geo();
+        let params = Encoders::new_target_encoder(1, 1.0);
+        let hierarchy = HierarchicalEncoder::<_, f64, f64>::fit(&data, &target, &[0, 1, 2], &mut params.clone());
+
+        let countries = ColumnTargetEncoder::<_, f64, f64>::fit(&data.column(0).to_owned(), &target, &hierarchy.params);
+        let fr = countries.encode(&"fr");
+        assert_eq!(hierarchy.encode(&["fr"]), fr);
+
+        let idf_stats: TargetStats<f64> = array![0.9, 0.7, 0.8].iter().copied().collect();
+        let idf = compute_encoding(&hierarchy.params.with_prior(fr), &idf_stats, 3).unwrap();
+        assert_abs_diff_eq!(hierarchy.encode(&["fr", "idf"]), idf, epsilon = 1e-12);
+        let versailles = compute_encoding(&hierarchy.params.with_prior(idf), &vec![0.8].into_iter().collect(), 5).unwrap();
+        assert_abs_diff_eq!(hierarchy.encode(&["fr", "idf", "versailles"]), versailles, epsilon = 1e-12);
+
+        // paris in the us is a different city, unseen categories back off to their nearest seen ancestor
+        assert_ne!(hierarchy.encode(&["us", "tx", "paris"]), hierarchy.encode(&["fr", "idf", "paris"]));
+        assert_eq!(hierarchy.encode(&["fr", "idf", "lyon"]), hierarchy.encode(&["fr", "idf"]));
+        assert_eq!(hierarchy.encode(&["fr", "bzh", "paris"]), fr);
+        assert_abs_diff_eq!(hierarchy.encode(&["de", "by", "munich"]), target.mean().unwrap(), epsilon = 1e-12);
+
+        let encoded = hierarchy.transform_to(array![["fr", "idf", "lyon"], ["us", "tx", "austin"]].view());
+        assert_eq!(encoded.row(0), array![fr, hierarchy.encode(&["fr", "idf"]), hierarchy.encode(&["fr", "idf"])]);
+        assert_eq!(encoded[[1, 2]], hierarchy.encode(&["us", "tx", "austin"]));
+    }
+
+    #[test]
+    fn test_partial_fit_and_min_count() {
+        let (data, target) = geo();
+        let params = Encoders::new_target_encoder(1, 1.0);
+        let hierarchy = HierarchicalEncoder::<_, f64, f64>::fit(&data, &target, &[0, 1, 2], &mut params.clone());
+
+        let mut partial = HierarchicalEncoder::<_, f64, f64>::fit(&data.slice(s![..3, ..]), &target.slice(s![..3]).to_owned(), &[0, 1, 2], &mut params.clone());
+        partial.partial_fit(&data.slice(s![3.., ..]), &target.slice(s![3..]).to_owned());
+        for (a, b) in partial.transform_to(data.view()).iter().zip(hierarchy.transform_to(data.view()).iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-12);
+        }
+
+        // versailles and paris in the us have a single row
+        let pruned = hierarchy.with_min_count(2);
+        assert_eq!(pruned.encode(&["fr", "idf", "versailles"]), pruned.encode(&["fr", "idf"]));
+        assert_eq!(pruned.encode(&["us", "tx", "paris"]), pruned.encode(&["us", "tx"]));
+        assert_ne!(pruned.encode(&["fr", "idf", "paris"]), pruned.encode(&["fr", "idf"]));
+    }
+
+    #[test]
+    fn test_deep_hierarchy() {
+        // each level of a path keeps a full code, however deep the hierarchy
+        let data = Array2::from_shape_fn((4, 64), |(row, _)| (row / 2).to_string());
+        let target = array![0.2, 0.3, 0.7, 0.6];
+        let mut hierarchy = HierarchicalEncoder::<_, f64, f64>::fit(&data, &target, &(0..64).collect_vec(), &mut Encoders::new_target_encoder(1, 1.0));
+        let batch = Array2::from_shape_fn((2, 64), |_| "2".to_owned());
+        hierarchy.partial_fit(&batch, &array![0.9, 0.8]);
+
+        let leaves = hierarchy.transform_to(ndarray::concatenate![Axis(0), data, batch].view()).column(63).to_owned();
+        for (row, leaf) in leaves.iter().enumerate() {
+            assert_abs_diff_eq!(hierarchy.encode(&vec![(row / 2).to_string(); 64]), leaf, epsilon = 1e-12);
+        }
+        assert!(leaves[0] < leaves[2] && leaves[2] < leaves[4]);
+    }
+}
@@ -0,0 +1,375 @@
+//! Target encoding of combinations of columns, see [`MatrixEncoder::fit_interactions`](crate::target_encoder::MatrixEncoder::fit_interactions).
+//!
+//! Categories of each column are replaced by integer codes, and the codes of the columns of an interaction form a single
+//! [`CompositeKey`], so composite categories are aggregated without cloning the categories or concatenating strings.
+use std::mem::size_of;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use ndarray::parallel::prelude::*;
+use ndarray::prelude::*;
+use num_traits::{Float, FromPrimitive};
+use smallvec::SmallVec;
+
+use crate::stats::TargetStats;
+use crate::target_encoder::{column_params, ColumnTargetEncoder, Data, Encoders, Key};
+
+/// Integer codes of the categories of one input column, shared by all interactions that use the column
+#[derive(Clone)]
+pub(crate) struct Dictionary<K> {
+    pub(crate) codes: FnvHashMap<K, u32>,
+    pub(crate) values: Vec<K>,
+}
+
+impl<K: Key> Dictionary<K> {
+    pub(crate) fn new() -> Dictionary<K> {
+        Dictionary { codes: FnvHashMap::default(), values: Vec::new() }
+    }
+
+    /// Code of `key`, which is added to the dictionary if it was not seen before
+    pub(crate) fn insert(&mut self, key: &K) -> u32 {
+        if let Some(&code) = self.codes.get(key) {
+            return code;
+        }
+        let code = self.values.len() as u32;
+        self.codes.insert(key.clone(), code);
+        self.values.push(key.clone());
+        code
+    }
+}
+
+/// Codes of the categories of several columns, the key of a combination of categories.
+/// Keys of up to 4 columns are stored inline, so there is no limit on the number of categories of a column.
+pub type CompositeKey = SmallVec<[u32; 4]>;
+
+/// Target encoding of the combinations of categories of several columns
+pub struct Interaction<D, T>
+where
+    D: Float + FromPrimitive,
+    T: Float,
+{
+    columns: Vec<usize>,
+    encoder: ColumnTargetEncoder<CompositeKey, D, T>,
+}
+
+impl<D: Data, T: Data> Interaction<D, T> {
+    /// Input columns combined by this interaction
+    pub fn columns(&self) -> &[usize] {
+        &self.columns
+    }
+
+    /// Encoder of the composite keys of the combinations
+    pub fn encoder(&self) -> &ColumnTargetEncoder<CompositeKey, D, T> {
+        &self.encoder
+    }
+}
+
+/// All interactions of a [`MatrixEncoder`](crate::target_encoder::MatrixEncoder) together with the dictionaries of their columns
+pub(crate) struct Interactions<K, D, T>
+where
+    D: Float + FromPrimitive,
+    T: Float,
+{
+    dictionaries: FnvHashMap<usize, Dictionary<K>>,
+    interactions: Vec<Interaction<D, T>>,
+}
+
+impl<K, D, T> Interactions<K, D, T>
+where
+    K: Key,
+    D: Data,
+    T: Data,
+{
+    pub(crate) fn new() -> Interactions<K, D, T> {
+        Interactions { dictionaries: FnvHashMap::default(), interactions: Vec::new() }
+    }
+
+    pub(crate) fn interactions(&self) -> &[Interaction<D, T>] {
+        &self.interactions
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.interactions.len()
+    }
+
+    /// Codes of `columns` of `data`, extending the dictionaries with categories not seen before
+    fn insert_codes(&mut self, data: ArrayView2<K>, columns: &FnvHashSet<usize>) -> FnvHashMap<usize, Vec<u32>> {
+        for &i in columns {
+            self.dictionaries.entry(i).or_insert_with(Dictionary::new);
+        }
+        self.dictionaries
+            .par_iter_mut()
+            .filter(|(i, _)| columns.contains(i))
+            .map(|(&i, dictionary)| (i, data.column(i).iter().map(|k| dictionary.insert(k)).collect()))
+            .collect()
+    }
+
+    /// Composite keys of the rows of an interaction of `columns`
+    fn composite_keys(codes: &FnvHashMap<usize, Vec<u32>>, columns: &[usize], num_rows: usize) -> Array1<CompositeKey> {
+        (0..num_rows)
+            .map(|row| columns.iter().map(|i| codes[i][row]).collect())
+            .collect()
+    }
+
+    /// Fit an encoder for each of `interactions`. Combinations with fewer than `min_support` rows are encoded with the prior.
+    /// If `skip_unsupported` is set, interactions without any combination that has `min_support` rows are left out.
+    /// Global statistics of `params` must be already computed.
+    pub(crate) fn fit(
+        &mut self,
+        data: ArrayView2<K>,
+        target: &Array1<T>,
+        interactions: &[Vec<usize>],
+        min_support: usize,
+        skip_unsupported: bool,
+        params: &Encoders<D>,
+    ) {
+        let columns: FnvHashSet<usize> = interactions.iter().flatten().copied().collect();
+        let codes = self.insert_codes(data, &columns);
+
+        let num_rows = data.len_of(Axis(0));
+        let fitted: Vec<Option<Interaction<D, T>>> = interactions.par_iter()
+            .map(|columns| {
+                let keys = Self::composite_keys(&codes, columns, num_rows);
+                let encoder = ColumnTargetEncoder::fit(&keys, target, params).with_min_count(min_support);
+                if skip_unsupported && encoder.stats().values().all(|s| s.count < min_support) {
+                    None
+                } else {
+                    Some(Interaction { columns: columns.clone(), encoder })
+                }
+            })
+            .collect();
+        self.interactions.extend(fitted.into_iter().flatten());
+    }
+
+    /// Update category statistics of all interactions with a new batch of rows, see [`ColumnTargetEncoder::partial_fit_weighted`]
+    pub(crate) fn partial_fit(&mut self, data: ArrayView2<K>, target: &Array1<T>, weights: Option<&Array1<D>>, target_stats: &TargetStats<D>) {
+        if self.interactions.is_empty() {
+            return;
+        }
+        let columns: FnvHashSet<usize> = self.dictionaries.keys().copied().collect();
+        let codes = self.insert_codes(data, &columns);
+
+        let num_rows = data.len_of(Axis(0));
+        self.interactions.par_iter_mut().for_each(|interaction| {
+            let keys = Self::composite_keys(&codes, &interaction.columns, num_rows);
+            let params = column_params(interaction.encoder.params(), target_stats);
+            interaction.encoder.partial_fit_weighted(&keys, target, weights, &params);
+        });
+    }
+
+    /// Recompute encodings of all interactions with new encoder parameters
+    pub(crate) fn with_params(&self, params: &Encoders<D>) -> Interactions<K, D, T> {
+        Interactions {
+            dictionaries: self.dictionaries.clone(),
+            interactions: self.interactions.par_iter()
+                .map(|interaction| Interaction {
+                    columns: interaction.columns.clone(),
+                    encoder: interaction.encoder.with_params(params),
+                })
+                .collect(),
+        }
+    }
+
+    /// Drop combinations of categories of all interactions, see [`ColumnTargetEncoder::compact`]
+    pub(crate) fn compact(&mut self, min_count: usize, tolerance: T) {
+        self.interactions.par_iter_mut().for_each(|interaction| { interaction.encoder.compact(min_count, tolerance); });
+    }
+
+    /// Same interactions with encodings stored as `O`, see [`ColumnTargetEncoder::with_output_type`]
+    pub(crate) fn with_output_type<O: Data>(self) -> Interactions<K, D, O> {
+        Interactions {
+            dictionaries: self.dictionaries,
+            interactions: self.interactions.into_iter()
+                .map(|interaction| Interaction { columns: interaction.columns, encoder: interaction.encoder.with_output_type() })
+                .collect(),
+        }
+    }
+
+    /// Approximate number of bytes used by the dictionaries and the encoders of the interactions
+    pub(crate) fn memory_size(&self) -> usize {
+        let dictionaries: usize = self.dictionaries.values()
+            .map(|dictionary| {
+                dictionary.codes.capacity() * (size_of::<K>() + size_of::<u32>() + 1) + dictionary.values.capacity() * size_of::<K>()
+            })
+            .sum();
+        let encoders: usize = self.interactions.iter().map(|interaction| interaction.encoder.memory_size()).sum();
+        dictionaries + encoders
+    }
+
+    /// Combine interactions fitted on disjoint sets of rows. Codes of `other` are translated to the codes of this one.
+    /// Interactions are matched by their columns. An interaction fitted by only one of them, e.g. because
+    /// [`fit_all_interactions`](crate::target_encoder::MatrixEncoder::fit_all_interactions) left it out of the other one,
+    /// is kept with the statistics of that one only, after the interactions of this one.
+    pub(crate) fn merge(mut self, other: Interactions<K, D, T>, target_stats: &TargetStats<D>) -> Interactions<K, D, T> {
+        let mut translations: FnvHashMap<usize, Vec<u32>> = FnvHashMap::default();
+        for (&i, other_dictionary) in other.dictionaries.iter() {
+            let dictionary = self.dictionaries.entry(i).or_insert_with(Dictionary::new);
+            translations.insert(i, other_dictionary.values.iter().map(|k| dictionary.insert(k)).collect());
+        }
+
+        // codes of `other` are translated to the codes of this one before merging
+        let translate = |interaction: Interaction<D, T>| {
+            let columns = interaction.columns;
+            let encoder = interaction.encoder.map_keys(|key| {
+                key.iter().zip(columns.iter()).map(|(&code, i)| translations[i][code as usize]).collect()
+            });
+            Interaction { columns, encoder }
+        };
+
+        let own_columns: FnvHashMap<&[usize], usize> = self.interactions.iter()
+            .enumerate()
+            .map(|(j, interaction)| (interaction.columns.as_slice(), j))
+            .collect();
+        let mut matched: Vec<Option<Interaction<D, T>>> = self.interactions.iter().map(|_| None).collect();
+        let mut other_only = Vec::new();
+        for interaction in other.interactions {
+            match own_columns.get(interaction.columns.as_slice()) {
+                Some(&j) => matched[j] = Some(interaction),
+                None => other_only.push(interaction),
+            }
+        }
+
+        self.interactions = self.interactions
+            .into_par_iter()
+            .zip(matched.into_par_iter())
+            .map(|(interaction, other_interaction)| {
+                let params = column_params(interaction.encoder.params(), target_stats);
+                let encoder = match other_interaction {
+                    Some(other_interaction) => interaction.encoder.merge(translate(other_interaction).encoder, &params),
+                    None => interaction.encoder.with_params(&params),
+                };
+                Interaction { columns: interaction.columns, encoder }
+            })
+            .chain(other_only.into_par_iter().map(|interaction| {
+                let interaction = translate(interaction);
+                let params = column_params(interaction.encoder.params(), target_stats);
+                Interaction { columns: interaction.columns, encoder: interaction.encoder.with_params(&params) }
+            }))
+            .collect();
+        self
+    }
+
+    /// Encode the interactions of `data` into the columns of `encoded`, one column per interaction
+    pub(crate) fn transform_into(&self, data: ArrayView2<K>, mut encoded: ArrayViewMut2<T>) {
+        let codes: FnvHashMap<usize, Vec<Option<u32>>> = self.dictionaries
+            .par_iter()
+            .map(|(&i, dictionary)| (i, data.column(i).iter().map(|k| dictionary.codes.get(k).copied()).collect()))
+            .collect();
+
+        encoded.axis_iter_mut(Axis(1))
+            .into_par_iter()
+            .zip(self.interactions.par_iter())
+            .for_each(|(mut encoded_column, interaction)| {
+                let prior = interaction.encoder.unknown_encoding();
+                for (row, e) in encoded_column.iter_mut().enumerate() {
+                    *e = match interaction.columns.iter().map(|i| codes[i][row]).collect::<Option<CompositeKey>>() {
+                        Some(key) => interaction.encoder.encode(&key),
+                        None => prior,
+                    };
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+    use ndarray::{array, s};
+    use crate::target_encoder::MatrixEncoder;
+
+    fn categories() -> (Array2<String>, Array1<f64>) {
+        let data = array![["a", "x", "1"], ["a", "y", "1"], ["b", "x", "2"], ["a", "x", "2"], ["b", "y", "1"], ["b", "x", "1"]];
+        (data.mapv(|s| s.to_owned()), array![0.2, 0.7, 0.4, 0.9, 0.1, 0.5])
+    }
+
+    #[test]
+    fn test_interaction_matches_composite_column() {
+        let (data, target) = categories();
+        let params = Encoders::new_target_encoder(1, 1.0);
+        let encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut params.clone())
+            .fit_interactions(&data, &target, &[vec![0, 1]], 2);
+
+        let composite = Array2::from_shape_fn((6, 1), |(row, _)| format!("{}{}", data[[row, 0]], data[[row, 1]]));
+        let expected = ColumnTargetEncoder::<_, f64, f64>::fit(&composite.column(0).to_owned(), &target, encoder.params())
+            .with_min_count(2);
+
+        let mut unseen = data.slice(s![..2, ..]).to_owned();
+        unseen[[1, 0]] = "c".to_owned();
+        let test = ndarray::concatenate![Axis(0), data, unseen];
+        let encoded = encoder.transform_to(test.view());
+        assert_eq!(encoded.dim(), (8, 4));
+        let without_interactions = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut params.clone());
+        assert_eq!(encoded.slice(s![.., ..3]), without_interactions.transform_to(test.view()));
+        for row in 0..6 {
+            assert_eq!(encoded[[row, 3]], expected.encode(&composite[[row, 0]]));
+        }
+        // ("a", "y") was seen once, which is below the support, ("c", "y") was never seen
+        assert_abs_diff_eq!(encoded[[1, 3]], target.mean().unwrap(), epsilon = 1e-12);
+        assert_eq!(encoded[[7, 3]], encoded[[1, 3]]);
+        assert_eq!(encoded[[6, 3]], expected.encode(&"ax".to_owned()));
+    }
+
+    #[test]
+    fn test_partial_fit_and_merge_match_fit() {
+        let (data, target) = categories();
+        for &min_support in &[1, 2] {
+            let fit = |rows: std::ops::Range<usize>| {
+                let (data, target) = (data.slice(s![rows.clone(), ..]).to_owned(), target.slice(s![rows]).to_owned());
+                MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0))
+                    .fit_interactions(&data, &target, &[vec![0, 1], vec![2, 0, 1]], min_support)
+            };
+            let expected = fit(0..6).transform_to(data.view());
+
+            let mut partial = fit(0..2);
+            partial.partial_fit(&data.slice(s![2.., ..]), &target.slice(s![2..]).to_owned());
+            let merged = fit(0..3).merge(fit(3..6));
+            for encoder in [partial, merged].iter() {
+                for (a, b) in encoder.transform_to(data.view()).iter().zip(expected.iter()) {
+                    assert_abs_diff_eq!(a, b, epsilon = 1e-12);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_interactions_chosen_on_each_shard() {
+        let (data, target) = categories();
+        let fit = |rows: std::ops::Range<usize>| {
+            let (data, target) = (data.slice(s![rows.clone(), ..]).to_owned(), target.slice(s![rows]).to_owned());
+            MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0))
+                .fit_all_interactions(&data, &target, 2, 2)
+        };
+        let (first, second) = (fit(0..2), fit(2..6));
+        assert_eq!(first.interactions().len(), 1);
+        assert_eq!(second.interactions().len(), 3);
+
+        // interactions of both shards are merged by their columns, the others are appended
+        let merged = first.merge(second);
+        assert_eq!(merged.interactions().iter().map(|i| i.columns().to_vec()).collect_vec(),
+                   vec![vec![0, 2], vec![0, 1], vec![1, 2]]);
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0))
+            .fit_interactions(&data, &target, &[vec![0, 2]], 2)
+            .transform_to(data.view());
+        let encoded = merged.transform_to(data.view());
+        assert_eq!(encoded.dim(), (6, 6));
+        for (a, b) in encoded.column(3).iter().zip(expected.column(3).iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_all_interactions_with_min_support() {
+        let (data, target) = categories();
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_columns(&data, &target, &[0, 1, 2], &mut Encoders::new_target_encoder(1, 1.0));
+        let all = encoder.with_params(encoder.params()).fit_all_interactions(&data, &target, 3, 1);
+        assert_eq!(all.num_output_columns(), 7);
+        assert_eq!(all.interactions().iter().map(|i| i.columns().to_vec()).collect_vec(),
+                   vec![vec![0, 1], vec![0, 2], vec![1, 2], vec![0, 1, 2]]);
+
+        // every pair of columns has a combination with 2 rows, no combination of all three columns does
+        let supported = encoder.fit_all_interactions(&data, &target, 3, 2);
+        assert_eq!(supported.interactions().iter().map(|i| i.columns().to_vec()).collect_vec(),
+                   vec![vec![0, 1], vec![0, 2], vec![1, 2]]);
+        assert_eq!(supported.transform_to(data.view()).dim(), (6, 6));
+    }
+}
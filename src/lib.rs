@@ -41,9 +41,78 @@
 //! encoder.transform(&mut data);
 //! ```
 //!
+//! Categories don't have to be floats: any `Hash + Eq + Ord` key such as `i64`, `u64` or `String` can be used,
+//! in which case [`transform_to`](target_encoder::MatrixEncoder::transform_to) returns the encodings in a new array.
+//! Encodings have the type of the target, so statistics can be computed in `f64` while the output is `f32`.
+//! ```rust ignore
+//! let ids = array![[10_i64, 7], [3, 7], [10, 1]];
+//! let target = array![0.5_f32, 0.1, 0.9];
+//!
+//! let encoder = MatrixEncoder::<_, f64, f32>::fit(&ids, &target, &mut Encoders::new_target_encoder(1, 1.0));
+//! let encoded: Array2<f32> = encoder.transform_to(ids.view());
+//! ```
+//!
+//! Matrices that mix numeric and categorical columns can be encoded without slicing them apart:
+//! [`fit_columns`](target_encoder::MatrixEncoder::fit_columns) encodes only the columns with the given indices,
+//! and `transform` keeps the other columns unchanged, or drops them with `with_remainder(Remainder::Drop)`.
+//! ```rust ignore
+//! let encoder = MatrixEncoder::<_, f64, f64>::fit_columns(&data, &target, &[0, 3], &mut Encoders::new_target_encoder(1, 1.0));
+//! encoder.transform(&mut data);
+//! ```
+//!
+//! The parameters passed to `fit` are the default for all columns.
+//! [`with_column_params`](target_encoder::MatrixEncoder::with_column_params) switches a single column to another encoder,
+//! recomputing its encodings from the collected statistics.
+//! ```rust ignore
+//! let encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 10.0))
+//!     .with_column_params(1, &Encoders::new_james_stein_encoder())
+//!     .with_column_params(2, &Encoders::new_count_encoder());
+//! ```
+//!
+//! Combinations of columns can be target-encoded as a single composite category with
+//! [`fit_interactions`](target_encoder::MatrixEncoder::fit_interactions), or for all combinations up to a given order with
+//! [`fit_all_interactions`](target_encoder::MatrixEncoder::fit_all_interactions). `transform_to` appends one column per interaction.
+//! ```rust ignore
+//! let encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0))
+//!     .fit_interactions(&data, &target, &[vec![0, 1]], 10);
+//! let encoded = encoder.transform_to(data.view()); // country, device, (country, device)
+//! ```
+//!
+//! Nested categories such as `country > region > city` can be encoded with a
+//! [`HierarchicalEncoder`](hierarchy::HierarchicalEncoder), where each level uses the encoding of its parent as the prior
+//! and unseen categories fall back to their nearest seen ancestor.
+//! ```rust ignore
+//! let geo = HierarchicalEncoder::<_, f64, f64>::fit(&data, &target, &[0, 1, 2], &mut Encoders::new_target_encoder(1, 1.0));
+//! let encoded = geo.transform_to(data.view()); // one column per level
+//! ```
+//!
+//! Fitted encoders of high-cardinality columns can be shrunk with [`compact`](target_encoder::MatrixEncoder::compact),
+//! which drops categories with little support or an encoding close to the one of unseen categories,
+//! and [`with_output_type`](target_encoder::MatrixEncoder::with_output_type) to store the encodings in a smaller float type.
+//! ```rust ignore
+//! let report = encoder.compact(10, 1e-4);
+//! println!("{} -> {} bytes", report.bytes_before, report.bytes_after);
+//! let encoder = encoder.with_output_type::<f32>();
+//! ```
+//!
+//! For scoring, the `frozen` feature adds [`freeze`](target_encoder::MatrixEncoder::freeze), which returns a read-only
+//! [`FrozenEncoder`](frozen::FrozenEncoder) that keeps the encodings of each column in flat sorted arrays instead of hash maps.
+//! ```rust ignore
+//! let frozen = encoder.freeze();
+//! let encoded = frozen.transform_to(data.view());
+//! ```
+//!
+//! Frozen encoders can be saved to a [model file](model_file) and opened with mmap, so that processes scoring with the
+//! same model share it in the page cache instead of each deserialising its own copy. Opening is `unsafe`,
+//! as the file must not be modified while it is mapped.
+//! ```rust ignore
+//! encoder.freeze().save("encoder.bin")?;
+//! let frozen = unsafe { FrozenEncoder::<String, f64>::open("encoder.bin")? };
+//! ```
+//!
 //! ## Python API
-//! Currently, the Python API supports only float32 and float64 data and targets,
-//! so you might need to convert your matrices before the encoding.
+//! Targets must be float32 or float64 arrays. Categories can be float, integer, boolean, string or object arrays,
+//! and pandas DataFrames, see the README for details.
 //!
 //! ```python ignore
 //! import blazing_encoders as be
@@ -54,8 +123,18 @@
 //!
 //! encoder = be.TargetEncoder_f64.fit(data, target) # you can use TargetEncoder_f32 for float32 data
 //! encoded_data = encoder.transform(data)
+//!
+//! # estimate smoothing from the data instead of setting it by hand
+//! encoder = be.TargetEncoder_f64.fit(data, target, smoothing="auto")
 //! ```
 //!
+//! With the `arrow` feature (`maturin build --release --features arrow`), `pyarrow.RecordBatch`es with integer,
+//! string and dictionary columns can be passed instead of numpy arrays. They are imported through the Arrow C Data Interface
+//! without copying, and dictionary columns are encoded through their dictionary codes.
+//!
+//! The `polars` feature adds the same for polars DataFrames: String and Categorical columns are encoded by default
+//! and a polars DataFrame is returned. In Rust it provides `MatrixEncoder::fit_dataframe` and `transform_dataframe`.
+//!
 //! # Limitations
 //! * Currently, only the basic target encoding is implemented
 //! * The library supports only continuous target variables. As a result, it can be used only in regression settings.
@@ -65,56 +144,232 @@
 extern crate approx;
 
 use itertools::Itertools;
-use ndarray::{Array2, ArrayView};
-use numpy::{IntoPyArray, PyArray, PyArray1, PyArray2, PyArrayDyn};
-use ordered_float::OrderedFloat;
+use ndarray::ArrayView;
+use numpy::{PyArray, PyArray1, PyArray2, PyArrayDyn};
 use pyo3::prelude::*;
 use ndarray::Dim;
 
-use crate::target_encoder::{ColumnTargetEncoder, MatrixEncoder, Encoders};
+#[cfg(not(test))]
+use crate::folds::FoldSplitter;
+#[cfg(not(test))]
+use crate::python::{extract_groups, fit_input, group_folds, output, transform_input, Categories, FrameColumns, KeyedEncoder};
+use crate::target_encoder::{ColumnTargetEncoder, Data, MatrixEncoder, Encoders, RareGrouping};
 
+pub mod folds;
+#[cfg(feature = "frozen")]
+pub mod frozen;
+pub mod hierarchy;
+pub mod interactions;
+#[cfg(feature = "frozen")]
+pub mod model_file;
 pub mod target_encoder;
 pub mod utils;
-mod stats;
+pub mod stats;
+#[cfg(feature = "arrow")]
+pub mod arrow_input;
+#[cfg(feature = "polars")]
+pub mod polars_input;
+#[cfg(not(test))] // https://github.com/PyO3/pyo3/issues/340
+#[macro_use]
+mod python;
 
 
+/// Build encoder parameters from Python arguments. `smoothing` is either a number or `"auto"`.
+#[cfg(not(test))] // https://github.com/PyO3/pyo3/issues/340
+fn encoder_params<T: Data>(smoothing: Option<&PyAny>, min_samples_leaf: usize) -> PyResult<Encoders<T>> {
+    let to_float = |x: f64| T::from_f64(x).expect("Converting smoothing to `T` must not fail.");
+    match smoothing {
+        None => Ok(Encoders::new_target_encoder(min_samples_leaf, to_float(1.0))),
+        Some(s) if s.extract::<&str>().map_or(false, |s| s == "auto") => Ok(Encoders::new_auto_target_encoder()),
+        Some(s) => Ok(Encoders::new_target_encoder(min_samples_leaf, to_float(s.extract::<f64>()?))),
+    }
+}
+
 macro_rules! create_target_encoder_class {
-    ($name:ident, $type:ty) => {
+    ($name:ident, $type:ty, $dtype:literal) => {
+        /// Categories can be float, integer, string or object numpy arrays, or pandas DataFrames
+        /// (and polars DataFrames with the `polars` feature).
+        /// For DataFrames, `columns` selects the encoded columns by name and `dtypes` by dtype;
+        /// by default object, category and string columns are encoded and the other columns are passed through.
         #[allow(non_camel_case_types)]
         #[pyclass]
         #[cfg(not(test))] // https://github.com/PyO3/pyo3/issues/340
         struct $name {
-            encoder: MatrixEncoder<$type, $type>
+            encoder: KeyedEncoder<$type>,
+            frame: Option<FrameColumns>,
+        }
+
+        #[cfg(not(test))] // https://github.com/PyO3/pyo3/issues/340
+        impl $name {
+            fn new(encoder: KeyedEncoder<$type>, frame: Option<FrameColumns>) -> Self {
+                let encoder = match &frame {
+                    Some(frame) => encoder.with_column_names(frame.encoded_names()),
+                    None => encoder,
+                };
+                $name { encoder, frame }
+            }
         }
 
         #[pymethods]
         #[cfg(not(test))] // https://github.com/PyO3/pyo3/issues/340
         impl $name {
+            /// `smoothing` is either a number or `"auto"` to estimate it from the data.
+            /// Categories with fewer than `min_frequency` rows, or beyond the `max_categories` most frequent ones,
+            /// are pooled into a shared "other" category, which also encodes categories not seen during fit.
+            #[staticmethod]
+            #[allow(clippy::too_many_arguments)] // one argument per Python keyword argument
+            #[args(smoothing="None", min_samples_leaf="2", columns="None", dtypes="None", min_frequency="1", max_categories="None")]
+            fn fit(py: Python, data: &PyAny, target: &PyArray1<$type>, smoothing: Option<&PyAny>, min_samples_leaf: usize, columns: Option<Vec<String>>, dtypes: Option<&PyAny>, min_frequency: usize, max_categories: Option<usize>) -> PyResult<Self> {
+                let mut params = encoder_params::<$type>(smoothing, min_samples_leaf)?;
+
+                let (frame, categories) = fit_input::<$type>(py, data, columns, dtypes, $dtype)?;
+                let target = target.to_owned_array();
+                let encoder = py.allow_threads(move || match categories {
+                    #[cfg(feature = "arrow")]
+                    Categories::Arrow(batch) => MatrixEncoder::fit_record_batch(&batch, &target, &mut params)
+                        .map(KeyedEncoder::Arrow)
+                        .map_err(python::arrow_error),
+                    categories => fit_categories!(categories, |data| (MatrixEncoder::fit(&data, &target, &mut params), ())).map(|(encoder, _)| encoder),
+                })?;
+                let encoder = match (min_frequency, max_categories) {
+                    (1, None) => encoder,
+                    _ => encoder.with_rare_grouping(RareGrouping { min_frequency, max_categories }),
+                };
+
+                Ok($name::new(encoder, frame))
+            }
+
+            /// Fit with rows weighted by an exponential decay on `timestamps` with the given `half_life`
+            #[staticmethod]
+            #[allow(clippy::too_many_arguments)] // one argument per Python keyword argument
+            #[args(smoothing="None", min_samples_leaf="2", columns="None", dtypes="None")]
+            fn fit_with_decay(py: Python, data: &PyAny, target: &PyArray1<$type>, timestamps: &PyArray1<$type>, half_life: f64, smoothing: Option<&PyAny>, min_samples_leaf: usize, columns: Option<Vec<String>>, dtypes: Option<&PyAny>) -> PyResult<Self> {
+                if !(half_life > 0.0) {
+                    return Err(pyo3::exceptions::PyValueError::new_err("`half_life` must be positive"));
+                }
+                let mut params = encoder_params::<$type>(smoothing, min_samples_leaf)?;
+
+                let (frame, categories) = fit_input::<$type>(py, data, columns, dtypes, $dtype)?;
+                let target = target.to_owned_array();
+                let timestamps = timestamps.to_owned_array();
+                let (encoder, _) = py.allow_threads(move || {
+                    fit_categories!(categories, |data| {
+                        (MatrixEncoder::fit_with_decay(&data, &target, &timestamps, half_life as $type, &mut params), ())
+                    })
+                })?;
+
+                Ok($name::new(encoder, frame))
+            }
+
+            /// Fit on all rows and return the encoder together with training rows encoded using only rows strictly earlier in `order`.
+            /// Rows of the earliest `order` have no history and are encoded with `initial_prior`, or NaN by default.
+            #[staticmethod]
+            #[allow(clippy::too_many_arguments)] // one argument per Python keyword argument
+            #[args(initial_prior="None", smoothing="None", min_samples_leaf="2", columns="None", dtypes="None")]
+            fn fit_transform_ordered(py: Python, data: &PyAny, target: &PyArray1<$type>, order: &PyArray1<$type>, initial_prior: Option<$type>, smoothing: Option<&PyAny>, min_samples_leaf: usize, columns: Option<Vec<String>>, dtypes: Option<&PyAny>) -> PyResult<(Self, PyObject)> {
+                let mut params = encoder_params::<$type>(smoothing, min_samples_leaf)?;
+
+                let (frame, categories) = fit_input::<$type>(py, data, columns, dtypes, $dtype)?;
+                let target = target.to_owned_array();
+                let order = order.to_owned_array();
+                let (encoder, encoded) = py.allow_threads(move || {
+                    fit_categories!(categories, |data| MatrixEncoder::fit_transform_ordered(&data, &target, &order, initial_prior, &mut params))
+                })?;
+
+                let encoded = output(py, data, frame.as_ref(), encoded)?;
+                Ok(($name::new(encoder, frame), encoded))
+            }
+
+            /// Fit on all rows and return the encoder together with out-of-fold encodings of the training rows.
+            /// Pass `groups` to keep all rows of a group in the same fold, or `stratify=True` for classification targets.
+            /// Groups can be integers, floats or strings like the categories of `data`.
             #[staticmethod]
-            #[args(smoothing="1.0", min_samples_leaf="2")]
-            unsafe fn fit(py: Python, data: &PyArray2<$type>, target: &PyArray1<$type>, smoothing:f64, min_samples_leaf: usize) -> Self {
-                let data = data.as_array_mut().mapv::<OrderedFloat<$type>, _>(OrderedFloat::from);
-                    // let target = target.as_slice().unwrap();
-                let target = target.as_array_mut();
-                let encoder = py.allow_threads(move || {
-                    MatrixEncoder::fit(&data, &target.to_owned(), &mut Encoders::<$type>::new_target_encoder(min_samples_leaf, smoothing as $type))
-                });
-
-                $name { encoder }
+            #[allow(clippy::too_many_arguments)] // one argument per Python keyword argument
+            #[args(n_folds="5", seed="0", groups="None", stratify="false", smoothing="None", min_samples_leaf="2", columns="None", dtypes="None")]
+            fn fit_transform_oof(py: Python, data: &PyAny, target: &PyArray1<$type>, n_folds: usize, seed: u64, groups: Option<&PyAny>, stratify: bool, smoothing: Option<&PyAny>, min_samples_leaf: usize, columns: Option<Vec<String>>, dtypes: Option<&PyAny>) -> PyResult<(Self, PyObject)> {
+                let mut params = encoder_params::<$type>(smoothing, min_samples_leaf)?;
+
+                let (frame, categories) = fit_input::<$type>(py, data, columns, dtypes, $dtype)?;
+                let target = target.to_owned_array();
+                let groups = groups.map(|groups| extract_groups::<$type>(py, groups, $dtype)).transpose()?;
+                let (encoder, encoded) = py.allow_threads(move || {
+                    let splitter = FoldSplitter::new(n_folds, seed);
+                    let folds = match groups {
+                        Some(groups) => group_folds(&splitter, groups),
+                        None if stratify => splitter.stratified_kfold(&target),
+                        None => splitter.kfold(target.len()),
+                    };
+                    fit_categories!(categories, |data| MatrixEncoder::fit_transform_oof(&data, &target, &folds, &mut params))
+                })?;
+
+                let encoded = output(py, data, frame.as_ref(), encoded)?;
+                Ok(($name::new(encoder, frame), encoded))
+            }
+
+            /// Update the fitted encoder with a new batch of rows. `timestamps` are required for encoders created with `fit_with_decay`.
+            #[args(timestamps="None")]
+            fn partial_fit(&mut self, py: Python, data: &PyAny, target: &PyArray1<$type>, timestamps: Option<&PyArray1<$type>>) -> PyResult<()> {
+                let categories = transform_input::<$type>(py, data, &self.encoder, self.frame.as_ref(), $dtype)?;
+                let target = target.to_owned_array();
+                let timestamps = timestamps.map(|t| t.to_owned_array());
+                self.encoder.partial_fit(py, categories, target, timestamps)
+            }
+
+            /// Recompute encodings with new `smoothing` and `min_samples_leaf` without refitting
+            #[args(smoothing="None", min_samples_leaf="2")]
+            fn with_params(&self, py: Python, smoothing: Option<&PyAny>, min_samples_leaf: usize) -> PyResult<Self> {
+                let params = encoder_params::<$type>(smoothing, min_samples_leaf)?;
+                Ok($name { encoder: self.encoder.with_params(py, &params), frame: self.frame.clone() })
+            }
+
+            /// Returns a DataFrame with the encoded columns replaced if the encoder was fitted on a DataFrame, otherwise a numpy array.
+            /// Pass a writeable `out` array with a row for each row of the data and a column for each output column,
+            /// not sharing memory with the data, to encode into it instead of allocating a new one.
+            /// Numpy arrays are read in place, so they must not be modified by other threads during the call.
+            #[args(out="None")]
+            fn transform(&self, py: Python, data: &PyAny, out: Option<&PyArray2<$type>>) -> PyResult<PyObject> {
+                let categories = transform_input::<$type>(py, data, &self.encoder, self.frame.as_ref(), $dtype)?;
+                match out {
+                    Some(out) => {
+                        if !out.getattr("flags")?.getattr("writeable")?.extract::<bool>()? {
+                            return Err(pyo3::exceptions::PyValueError::new_err("`out` must be writeable"));
+                        }
+                        self.encoder.check_output(&categories, out)?;
+                        self.encoder.transform_into(py, categories, unsafe { out.as_array_mut() })?;
+                        Ok(out.to_object(py))
+                    },
+                    None => {
+                        let encoded = self.encoder.transform(py, categories)?;
+                        output(py, data, self.frame.as_ref(), encoded)
+                    },
+                }
+            }
+
+            /// Drop categories with fewer than `min_count` rows or with an encoding within `tolerance` of the encoding of
+            /// unseen categories, which they are encoded with from then on. Returns the number of categories and the
+            /// approximate size in bytes of the encoder before and after.
+            #[args(min_count="1", tolerance="0.0")]
+            fn compact(&mut self, py: Python, min_count: usize, tolerance: $type) -> PyResult<PyObject> {
+                let report = self.encoder.compact(py, min_count, tolerance);
+                let dict = pyo3::types::PyDict::new(py);
+                dict.set_item("categories_before", report.categories_before)?;
+                dict.set_item("categories_after", report.categories_after)?;
+                dict.set_item("bytes_before", report.bytes_before)?;
+                dict.set_item("bytes_after", report.bytes_after)?;
+                Ok(dict.to_object(py))
             }
 
-            unsafe fn transform(&self, py: Python, data: &PyArray2<$type>) -> Py<PyArray2<$type>> {
-                let mut data = data.as_array_mut().mapv::<OrderedFloat<$type>, _>(OrderedFloat::from);
-                self.encoder.transform(&mut data);
-                Array2::from(data).map(|x| x.0).into_pyarray(py).to_owned()
+            /// Fitted encodings as a list with a `{category: encoding}` dict for each column
+            fn mapping(&self, py: Python) -> PyResult<PyObject> {
+                self.encoder.mapping(py)
             }
         }
 
     }
 }
 
-create_target_encoder_class!(TargetEncoder_f64, f64);
-create_target_encoder_class!(TargetEncoder_f32, f32);
+create_target_encoder_class!(TargetEncoder_f64, f64, "float64");
+create_target_encoder_class!(TargetEncoder_f32, f32, "float32");
 
 #[pymodule]
 #[cfg(not(test))] // https://github.com/PyO3/pyo3/issues/340
@@ -0,0 +1,400 @@
+//! Binary model files of [`FrozenEncoder`]s. [`FrozenEncoder::open`] memory-maps the file and encodes straight from it,
+//! so processes scoring with the same model share it in the page cache and start without deserialising it.
+//!
+//! The file is a sequence of 8-byte words and arrays in native byte order, each array padded to a multiple of 8 bytes:
+//! * header: magic, format version, byte order mark, kind of keys, size of encodings, remainder,
+//!   number of input columns, number of encoded columns and the default encoder parameters
+//! * a descriptor per encoded column: input column, number of categories, encoding of unknown categories as `f64`,
+//!   length of the category bytes, length of the column name, `u64::MAX` if the column has none, and the encoder
+//!   parameters of the column
+//! * arrays of each column: name, search keys, encodings, and the offsets and bytes of the categories
+//!
+//! Encoder parameters are stored as the kind of encoder, `min_samples_leaf`, `smoothing`, prior, global target variance
+//! and global target count, floats as `f64` and `u64::MAX` for the ones the kind of encoder doesn't have.
+//! They say how the model was fitted, see [`FrozenEncoder::params`]. Category statistics are left out, so an opened
+//! encoder can't be refitted or updated. Interactions are not supported either,
+//! [`freeze`](crate::target_encoder::MatrixEncoder::freeze) panics on encoders fitted with interactions.
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytemuck::Pod;
+use memmap2::Mmap;
+
+use crate::frozen::{Buffer, FrozenColumn, FrozenEncoder};
+use crate::target_encoder::{Data, Encoders, FrozenKey, Key, Remainder, FLOAT_KEYS, OPTIONAL_STRING_KEYS, SIGNED_KEYS, STRING_KEYS, UNSIGNED_KEYS};
+
+const MAGIC: [u8; 8] = *b"BLZENC\0\0";
+const VERSION: u64 = 2;
+/// Reads differently on a machine with another byte order
+const BYTE_ORDER: u64 = 0x0102_0304_0506_0708;
+const PARAMS_WORDS: u64 = 6;
+const HEADER_WORDS: u64 = 8 + PARAMS_WORDS;
+const DESCRIPTOR_WORDS: u64 = 5 + PARAMS_WORDS;
+/// Length of absent category bytes and column names, and absent encoder parameters
+const NONE: u64 = u64::MAX;
+
+const TARGET_ENCODER: u64 = 1;
+const JAMES_STEIN_ENCODER: u64 = 2;
+const AUTO_TARGET_ENCODER: u64 = 3;
+const COUNT_ENCODER: u64 = 4;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn key_kind_name(kind: u64) -> String {
+    match kind {
+        SIGNED_KEYS => "signed integer".to_string(),
+        UNSIGNED_KEYS => "unsigned integer".to_string(),
+        FLOAT_KEYS => "float".to_string(),
+        STRING_KEYS => "string".to_string(),
+        OPTIONAL_STRING_KEYS => "optional string".to_string(),
+        #[cfg(feature = "arrow")]
+        crate::arrow_input::ARROW_KEYS => "Arrow".to_string(),
+        kind => format!("unknown ({})", kind),
+    }
+}
+
+fn float_word(value: Option<f64>) -> u64 {
+    value.map_or(NONE, f64::to_bits)
+}
+
+fn word_float(word: u64) -> Option<f64> {
+    if word == NONE { None } else { Some(f64::from_bits(word)) }
+}
+
+fn word_count(word: u64) -> Option<usize> {
+    if word == NONE { None } else { usize::try_from(word).ok() }
+}
+
+fn params_words(params: &Encoders<f64>) -> [u64; PARAMS_WORDS as usize] {
+    match *params {
+        Encoders::TargetEncoder { min_samples_leaf, smoothing, prior } => {
+            [TARGET_ENCODER, min_samples_leaf as u64, smoothing.to_bits(), float_word(prior), NONE, NONE]
+        }
+        Encoders::JamesSteinEncoder { prior, global_target_var, global_target_count } => [
+            JAMES_STEIN_ENCODER, NONE, NONE, float_word(prior), float_word(global_target_var),
+            global_target_count.map_or(NONE, |count| count as u64),
+        ],
+        Encoders::AutoTargetEncoder { prior, global_target_var } => {
+            [AUTO_TARGET_ENCODER, NONE, NONE, float_word(prior), float_word(global_target_var), NONE]
+        }
+        Encoders::CountEncoder { prior } => [COUNT_ENCODER, NONE, NONE, float_word(prior), NONE, NONE],
+    }
+}
+
+/// Encoder parameters stored by [`params_words`], `None` if they are corrupt
+fn read_params(words: &[u64]) -> Option<Encoders<f64>> {
+    let (prior, global_target_var) = (word_float(words[3]), word_float(words[4]));
+    match words[0] {
+        TARGET_ENCODER => Some(Encoders::TargetEncoder {
+            min_samples_leaf: word_count(words[1])?,
+            smoothing: word_float(words[2])?,
+            prior,
+        }),
+        JAMES_STEIN_ENCODER => Some(Encoders::JamesSteinEncoder { prior, global_target_var, global_target_count: word_count(words[5]) }),
+        AUTO_TARGET_ENCODER => Some(Encoders::AutoTargetEncoder { prior, global_target_var }),
+        COUNT_ENCODER => Some(Encoders::CountEncoder { prior }),
+        _ => None,
+    }
+}
+
+/// Write `bytes` followed by zeros up to a multiple of 8 bytes
+fn write_padded<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(bytes)?;
+    writer.write_all(&[0; 8][..(8 - bytes.len() % 8) % 8])
+}
+
+/// Sequential reader of the parts of a mapped model file, which checks that each part is within the file
+struct Reader<'a> {
+    map: &'a Arc<Mmap>,
+    position: usize,
+}
+
+impl Reader<'_> {
+    /// Start of the next `len` bytes, which are skipped along with their padding
+    fn skip(&mut self, len: u64, part: &str) -> io::Result<usize> {
+        let start = self.position;
+        let end = usize::try_from(len).ok()
+            .and_then(|len| start.checked_add(len))
+            .filter(|&end| end <= self.map.len())
+            .ok_or_else(|| invalid_data(format!("Model file is truncated, it ends within the {}", part)))?;
+        self.position = end + (8 - end % 8) % 8;
+        Ok(start)
+    }
+
+    fn words(&mut self, len: u64, part: &str) -> io::Result<Vec<u64>> {
+        let num_bytes = len.checked_mul(8).ok_or_else(|| invalid_data(format!("Model file has a corrupt {}", part)))?;
+        let start = self.skip(num_bytes, part)?;
+        Ok(self.map[start..start + num_bytes as usize]
+            .chunks_exact(8)
+            .map(|word| u64::from_ne_bytes(<[u8; 8]>::try_from(word).unwrap()))
+            .collect())
+    }
+
+    fn array<V: Pod>(&mut self, len: u64, part: &str) -> io::Result<Buffer<V>> {
+        let num_bytes = len.checked_mul(size_of::<V>() as u64).ok_or_else(|| invalid_data(format!("Model file has a corrupt {}", part)))?;
+        let start = self.skip(num_bytes, part)?;
+        Ok(Buffer::Mapped { map: Arc::clone(self.map), start, len: len as usize })
+    }
+}
+
+impl<K: Key + FrozenKey, T: Data + Pod> FrozenEncoder<K, T> {
+    /// Write this encoder to a model file at `path`, which can be opened with [`open`](FrozenEncoder::open)
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Write this encoder in the model file format to `writer`
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let remainder = match self.remainder {
+            Remainder::Passthrough => 0,
+            Remainder::Drop => 1,
+        };
+        let mut words = vec![
+            u64::from_ne_bytes(MAGIC), VERSION, BYTE_ORDER, K::KEY_KIND, size_of::<T>() as u64, remainder,
+            self.num_input_columns as u64, self.columns.len() as u64,
+        ];
+        words.extend_from_slice(&params_words(&self.params));
+        for (j, column) in self.columns.iter().enumerate() {
+            words.extend_from_slice(&[
+                self.input_columns[j] as u64,
+                column.len() as u64,
+                column.unknown.to_f64().unwrap().to_bits(),
+                if column.offsets.is_empty() { NONE } else { column.bytes.len() as u64 },
+                self.column_names.as_ref().map_or(NONE, |names| names[j].len() as u64),
+            ]);
+            words.extend_from_slice(&params_words(&column.params));
+        }
+        writer.write_all(bytemuck::cast_slice(&words))?;
+
+        for (j, column) in self.columns.iter().enumerate() {
+            if let Some(names) = &self.column_names {
+                write_padded(&mut writer, names[j].as_bytes())?;
+            }
+            write_padded(&mut writer, bytemuck::cast_slice(&column.search_keys))?;
+            write_padded(&mut writer, bytemuck::cast_slice(&column.encodings))?;
+            if !column.offsets.is_empty() {
+                write_padded(&mut writer, bytemuck::cast_slice(&column.offsets))?;
+                write_padded(&mut writer, &column.bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Open a model file written by [`save`](FrozenEncoder::save) without reading it into memory. Fails with
+    /// [`io::ErrorKind::InvalidData`] if the file is truncated or corrupt, or if it holds another kind of keys or
+    /// encodings than `K` and `T`.
+    ///
+    /// # Safety
+    /// The file is memory-mapped, so it must not be modified or truncated, by this or any other process, while the
+    /// returned encoder or any of its columns are alive. Otherwise encoding reads changed or unmapped memory,
+    /// which is undefined behaviour.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<FrozenEncoder<K, T>> {
+        let file = File::open(path)?;
+        let map = Arc::new(Mmap::map(&file)?);
+        Self::from_map(&map)
+    }
+
+    fn from_map(map: &Arc<Mmap>) -> io::Result<FrozenEncoder<K, T>> {
+        let mut reader = Reader { map, position: 0 };
+        let header = reader.words(HEADER_WORDS, "header")?;
+        if header[0] != u64::from_ne_bytes(MAGIC) {
+            return Err(invalid_data("Not a model file".to_string()));
+        }
+        if header[1] != VERSION {
+            return Err(invalid_data(format!("Model file has format version {}, but only version {} is supported", header[1], VERSION)));
+        }
+        if header[2] != BYTE_ORDER {
+            return Err(invalid_data("Model file was written on a machine with another byte order".to_string()));
+        }
+        if header[3] != K::KEY_KIND {
+            return Err(invalid_data(format!(
+                "Model file has {} keys, but {} keys were expected", key_kind_name(header[3]), key_kind_name(K::KEY_KIND)
+            )));
+        }
+        if header[4] != size_of::<T>() as u64 {
+            return Err(invalid_data(format!(
+                "Model file has {}-byte encodings, but {}-byte encodings were expected", header[4], size_of::<T>()
+            )));
+        }
+        let remainder = match header[5] {
+            0 => Remainder::Passthrough,
+            1 => Remainder::Drop,
+            _ => return Err(invalid_data("Model file has a corrupt header".to_string())),
+        };
+        let (num_input_columns, num_columns) = (header[6], header[7]);
+        let params = read_params(&header[8..]).ok_or_else(|| invalid_data("Model file has corrupt encoder parameters".to_string()))?;
+
+        let descriptors = reader.words(num_columns.saturating_mul(DESCRIPTOR_WORDS), "column descriptors")?;
+        let mut input_columns = Vec::with_capacity(num_columns as usize);
+        let mut names = Vec::with_capacity(num_columns as usize);
+        let mut columns = Vec::with_capacity(num_columns as usize);
+        for (j, descriptor) in descriptors.chunks_exact(DESCRIPTOR_WORDS as usize).enumerate() {
+            let (input_column, len, unknown, bytes_len, name_len) = (descriptor[0], descriptor[1], descriptor[2], descriptor[3], descriptor[4]);
+            if input_column >= num_input_columns {
+                return Err(invalid_data(format!("Model file has a corrupt descriptor of column {}", j)));
+            }
+            input_columns.push(input_column as usize);
+            let column_params = read_params(&descriptor[5..])
+                .ok_or_else(|| invalid_data(format!("Model file has corrupt encoder parameters of column {}", j)))?;
+
+            if name_len != NONE {
+                let start = reader.skip(name_len, &format!("name of column {}", j))?;
+                let name = std::str::from_utf8(&map[start..start + name_len as usize])
+                    .map_err(|_| invalid_data(format!("Model file has a corrupt name of column {}", j)))?;
+                names.push(name.to_string());
+            }
+            let search_keys = reader.array(len, &format!("search keys of column {}", j))?;
+            let encodings = reader.array(len, &format!("encodings of column {}", j))?;
+            let (offsets, bytes) = if bytes_len == NONE {
+                (Buffer::Owned(Vec::new()), Buffer::Owned(Vec::new()))
+            } else {
+                let offsets = reader.array(len.saturating_add(1), &format!("category offsets of column {}", j))?;
+                (offsets, reader.array(bytes_len, &format!("category bytes of column {}", j))?)
+            };
+            columns.push(FrozenColumn {
+                search_keys,
+                encodings,
+                offsets,
+                bytes,
+                unknown: T::from(f64::from_bits(unknown)).unwrap(),
+                params: column_params,
+                phantom_key: PhantomData,
+            });
+        }
+
+        if reader.position > map.len() {
+            return Err(invalid_data("Model file is truncated, it ends within the padding of the last column".to_string()));
+        }
+        if reader.position < map.len() {
+            return Err(invalid_data(format!("Model file is corrupt, it has {} bytes after the last column", map.len() - reader.position)));
+        }
+        if !names.is_empty() && names.len() != columns.len() {
+            return Err(invalid_data("Model file has names for only some of the columns".to_string()));
+        }
+
+        Ok(FrozenEncoder {
+            params,
+            column_names: if names.is_empty() { None } else { Some(names) },
+            input_columns,
+            num_input_columns: num_input_columns as usize,
+            remainder,
+            columns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::prelude::*;
+    use std::path::PathBuf;
+
+    use crate::target_encoder::{Encoders, MatrixEncoder};
+
+    /// Model file in the temporary directory, removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            TempFile(std::env::temp_dir().join(format!("blazing_encoders_{}_{}.bin", name, std::process::id())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn string_encoder() -> (Array2<String>, MatrixEncoder<String, f64, f64>) {
+        let data = array![["a", "x", "1"], ["b", "x", "2"], ["a", "y", "3"], ["c", "y", "4"], ["a", "x", "5"]].mapv(|k| k.to_string());
+        let target = array![0.1, 0.9, 0.4, 0.6, 0.2];
+        let encoder = MatrixEncoder::fit_columns(&data, &target, &[0, 1], &mut Encoders::new_target_encoder(1, 1.0))
+            .with_column_params(1, &Encoders::new_james_stein_encoder())
+            .with_remainder(Remainder::Drop)
+            .with_column_names(vec!["city".to_string(), "device".to_string()]);
+        (data, encoder)
+    }
+
+    fn open_error<K: Key + FrozenKey, T: Data + Pod>(bytes: &[u8], name: &str) -> String {
+        let file = TempFile::new(name);
+        std::fs::write(&file.0, bytes).unwrap();
+        let error = unsafe { FrozenEncoder::<K, T>::open(&file.0) }.err().expect("Opening a broken model file must fail");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        error.to_string()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (data, encoder) = string_encoder();
+        let file = TempFile::new("round_trip");
+        encoder.freeze().save(&file.0).unwrap();
+
+        let opened = unsafe { FrozenEncoder::<String, f64>::open(&file.0) }.unwrap();
+        let unseen = array![["a", "", "1"], ["d", "y", "2"]].mapv(|k| k.to_string());
+        assert_eq!(opened.transform_to(data.view()), encoder.transform_to(data.view()));
+        assert_eq!(opened.transform_to(unseen.view()), encoder.transform_to(unseen.view()));
+        assert_eq!(opened.column_names(), encoder.column_names());
+        assert_eq!(opened.input_columns(), &[0, 1]);
+        assert_eq!(opened.params(), encoder.params());
+        for (column, fitted) in opened.columns().iter().zip(encoder.columns()) {
+            assert_eq!(column.params(), fitted.params());
+        }
+        assert!(matches!(opened.columns()[1].params(), Encoders::JamesSteinEncoder { global_target_count: Some(5), .. }));
+
+        let ints = array![[3_i64, -1], [4, -1], [3, 7]];
+        let int_encoder = MatrixEncoder::<_, f32, f32>::fit_columns(&ints, &array![1., 2., 4.], &[0], &mut Encoders::new_target_encoder(1, 1.0));
+        let int_file = TempFile::new("round_trip_ints");
+        int_encoder.freeze().save(&int_file.0).unwrap();
+        let opened = unsafe { FrozenEncoder::<i64, f32>::open(&int_file.0) }.unwrap();
+        assert_eq!(opened.transform_to(ints.view()), int_encoder.transform_to(ints.view()));
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let (_, encoder) = string_encoder();
+        let mut bytes = Vec::new();
+        encoder.freeze().write(&mut bytes).unwrap();
+
+        for &len in &[0, 20, 8 * (HEADER_WORDS as usize + 3), bytes.len() - 9, bytes.len() - 1] {
+            let error = open_error::<String, f64>(&bytes[..len], "truncated");
+            assert!(error.starts_with("Model file is truncated"), "{}", error);
+        }
+        let error = open_error::<String, f64>(&bytes[..8 * (HEADER_WORDS as usize + 3)], "truncated");
+        assert_eq!(error, "Model file is truncated, it ends within the column descriptors");
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        let (_, encoder) = string_encoder();
+        let mut bytes = Vec::new();
+        encoder.freeze().write(&mut bytes).unwrap();
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(open_error::<String, f64>(&magic, "magic"), "Not a model file");
+        assert_eq!(open_error::<i64, f64>(&bytes, "key_kind"), "Model file has string keys, but signed integer keys were expected");
+        assert_eq!(open_error::<String, f32>(&bytes, "value_size"), "Model file has 8-byte encodings, but 4-byte encodings were expected");
+
+        let mut input_column = bytes.clone();
+        input_column[8 * HEADER_WORDS as usize..][..8].copy_from_slice(&7_u64.to_ne_bytes());
+        assert_eq!(open_error::<String, f64>(&input_column, "input_column"), "Model file has a corrupt descriptor of column 0");
+
+        let mut params = bytes.clone();
+        params[8 * 8..][..8].copy_from_slice(&9_u64.to_ne_bytes());
+        assert_eq!(open_error::<String, f64>(&params, "params"), "Model file has corrupt encoder parameters");
+
+        let mut trailing = bytes.clone();
+        trailing.extend_from_slice(&[0; 8]);
+        assert_eq!(open_error::<String, f64>(&trailing, "trailing"), "Model file is corrupt, it has 8 bytes after the last column");
+    }
+}
@@ -0,0 +1,184 @@
+//! Fitting and transforming polars `DataFrame`s, enabled with the `polars` feature.
+//!
+//! Categorical, String, integer and boolean columns are supported. Categories are stored as [`ArrowKey`]s,
+//! so an encoder fitted on a polars `DataFrame` can also transform Arrow record batches and vice versa.
+//! Categorical columns are aggregated and encoded through their physical codes instead of hashing the strings of each row.
+use std::sync::Arc;
+
+use fnv::FnvHashMap;
+use ndarray::parallel::prelude::*;
+use ndarray::prelude::*;
+use polars_core::prelude::{polars_bail, CategoricalChunked, Column, DataFrame, DataType, NamedFrom, PolarsResult, RevMapping, Series};
+
+use crate::arrow_input::{string_keys, ArrowKey};
+use crate::stats::TargetStats;
+use crate::target_encoder::{ColumnTargetEncoder, Data, Encoders, MatrixEncoder};
+
+/// Position of each row's category in the categories of `column`, `None` for null rows
+fn category_indices(column: &CategoricalChunked) -> PolarsResult<Vec<Option<usize>>> {
+    let rev_map = column.get_rev_map();
+    column.physical()
+        .iter()
+        .map(|code| match (code, rev_map.as_ref()) {
+            (None, _) => Ok(None),
+            (Some(code), RevMapping::Global(local, _, _)) => match local.get(&code) {
+                Some(&index) => Ok(Some(index as usize)),
+                None => polars_bail!(ComputeError: "global categorical code {} is not in the categories of the column", code),
+            },
+            (Some(code), RevMapping::Local(_, _)) => Ok(Some(code as usize)),
+        })
+        .collect()
+}
+
+/// Category statistics of a categorical column, collected in a dense vector indexed by category
+fn categorical_stats<D: Data, T: Data>(column: &CategoricalChunked, target: &Array1<T>) -> PolarsResult<FnvHashMap<ArrowKey, TargetStats<D>>> {
+    let categories = column.get_rev_map().get_categories();
+    let mut category_stats: Vec<TargetStats<D>> = vec![TargetStats::new(); categories.len()];
+    let mut null_stats = TargetStats::new();
+    for (index, t) in category_indices(column)?.into_iter().zip(target.iter()) {
+        let t = D::from(*t).unwrap();
+        match index {
+            Some(index) => category_stats[index].push(t),
+            None => null_stats.push(t),
+        }
+    }
+
+    let mut stats: FnvHashMap<ArrowKey, TargetStats<D>> = category_stats.into_iter()
+        .enumerate()
+        .filter(|(_, s)| s.count > 0)
+        .map(|(index, s)| (ArrowKey::Str(Arc::from(categories.value(index))), s))
+        .collect();
+    if null_stats.count > 0 {
+        stats.insert(ArrowKey::Null, null_stats);
+    }
+    Ok(stats)
+}
+
+/// Categories of all rows of a String, integer or boolean `column`
+fn column_keys(column: &Column) -> PolarsResult<Array1<ArrowKey>> {
+    let dtype = column.dtype();
+    if dtype.is_string() {
+        Ok(string_keys(column.str()?.iter()))
+    } else if dtype.is_categorical() || dtype.is_enum() {
+        let categorical = column.categorical()?;
+        let categories = categorical.get_rev_map().get_categories();
+        Ok(string_keys(category_indices(categorical)?.into_iter().map(|index| index.map(|i| categories.value(i)))))
+    } else if dtype.is_integer() || dtype == &DataType::Boolean {
+        let column = column.cast(&DataType::Int64)?;
+        Ok(column.i64()?.iter().map(|i| i.map_or(ArrowKey::Null, ArrowKey::Int)).collect())
+    } else {
+        polars_bail!(InvalidOperation: "columns of type {} can not be used as categories", dtype)
+    }
+}
+
+/// Encode all rows of `column` with `encoder`
+fn encode_column<D: Data, T: Data>(column: &Column, encoder: &ColumnTargetEncoder<ArrowKey, D, T>) -> PolarsResult<Vec<T>> {
+    let dtype = column.dtype();
+    let encoded = if dtype.is_categorical() || dtype.is_enum() {
+        // encode each category once, then look rows up by their codes
+        let categorical = column.categorical()?;
+        let categories = categorical.get_rev_map().get_categories();
+        let category_encodings: Vec<T> = categories.values_iter().map(|c| encoder.encode(&ArrowKey::Str(Arc::from(c)))).collect();
+        let null_encoding = encoder.encode(&ArrowKey::Null);
+        category_indices(categorical)?.into_iter().map(|index| index.map_or(null_encoding, |i| category_encodings[i])).collect()
+    } else {
+        column_keys(column)?.iter().map(|k| encoder.encode(k)).collect()
+    };
+    Ok(encoded)
+}
+
+impl<D, T> MatrixEncoder<ArrowKey, D, T>
+where
+    T: Data,
+    D: Data,
+{
+    /// Fit an encoder on the `columns` of `df`. The column names are kept for [`transform_dataframe`](MatrixEncoder::transform_dataframe).
+    pub fn fit_dataframe(df: &DataFrame, columns: &[&str], target: &Array1<T>, encoder: &mut Encoders<D>) -> PolarsResult<MatrixEncoder<ArrowKey, D, T>> {
+        assert_eq!(df.height(), target.len(), "DataFrame must have a target value for each row");
+        let target_stats: TargetStats<D> = target.iter().map(|t| D::from(*t).unwrap()).collect();
+        encoder.set_global_stats(&target_stats);
+
+        let selected = columns.iter().map(|name| df.column(name)).collect::<PolarsResult<Vec<&Column>>>()?;
+        let encodings = selected.into_par_iter()
+            .map(|column| {
+                let dtype = column.dtype();
+                if dtype.is_categorical() || dtype.is_enum() {
+                    Ok(ColumnTargetEncoder::from_stats(categorical_stats(column.categorical()?, target)?, encoder))
+                } else {
+                    Ok(ColumnTargetEncoder::fit(&column_keys(column)?, target, encoder))
+                }
+            })
+            .collect::<PolarsResult<Vec<_>>>()?;
+
+        let names = columns.iter().map(|name| name.to_string()).collect();
+        Ok(MatrixEncoder::from_columns(encoder.clone(), target_stats, encodings).with_column_names(names))
+    }
+
+    /// Copy of `df` with the columns the encoder was fitted on replaced by their encodings, `Float64` for `f64` and `Float32` for `f32`.
+    /// Other columns are passed through unchanged.
+    pub fn transform_dataframe(&self, df: &DataFrame) -> PolarsResult<DataFrame>
+    where
+        Series: NamedFrom<Vec<T>, [T]>,
+    {
+        let names = match self.column_names() {
+            Some(names) => names,
+            None => polars_bail!(InvalidOperation: "encoder was not fitted on named columns, use `fit_dataframe`"),
+        };
+
+        let encoded = names.par_iter()
+            .zip(self.columns().par_iter())
+            .map(|(name, encoder)| encode_column(df.column(name)?, encoder))
+            .collect::<PolarsResult<Vec<Vec<T>>>>()?;
+
+        let mut transformed = df.clone();
+        for (name, values) in names.iter().zip(encoded) {
+            transformed.replace(name, Series::new(name.as_str().into(), values))?;
+        }
+        Ok(transformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+    use polars_core::df;
+
+    #[test]
+    fn test_dataframe_matches_array_fit() {
+        let mut df = df! {
+            "city" => ["a", "b", "a", "c", "b", "a"],
+            "id" => [Some(7_i64), Some(7), Some(3), Some(3), None, Some(7)],
+            "price" => [1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        }.unwrap();
+        let categorical = df.column("city").unwrap().cast(&DataType::Categorical(None, Default::default())).unwrap();
+        df.with_column(categorical.with_name("city_cat".into())).unwrap();
+        let target = array![0.4, 0.9, 0.1, 0.3, 0.8, 0.5];
+
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_dataframe(&df, &["city", "id", "city_cat"], &target, &mut Encoders::new_target_encoder(1, 1.0)).unwrap();
+        let transformed = encoder.transform_dataframe(&df).unwrap();
+
+        let keys = Array2::from_shape_fn((6, 3), |(row, column)| {
+            let name = ["city", "id", "city_cat"][column];
+            column_keys(df.column(name).unwrap()).unwrap()[row].clone()
+        });
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&keys, &target, &mut Encoders::new_target_encoder(1, 1.0))
+            .transform_to(keys.view());
+
+        for (column, name) in ["city", "id", "city_cat"].iter().enumerate() {
+            let encoded: Vec<f64> = transformed.column(name).unwrap().f64().unwrap().into_no_null_iter().collect();
+            for (a, b) in encoded.iter().zip(expected.column(column).iter()) {
+                assert_abs_diff_eq!(a, b, epsilon = 1e-12);
+            }
+        }
+        assert_eq!(transformed.column("price").unwrap(), df.column("price").unwrap());
+    }
+
+    #[test]
+    fn test_dataframe_output_type() {
+        let df = df! { "city" => ["a", "b", "a"] }.unwrap();
+        let encoder = MatrixEncoder::<_, f32, f32>::fit_dataframe(&df, &["city"], &array![1.0, 0.0, 1.0], &mut Encoders::new_target_encoder(1, 1.0)).unwrap();
+        let transformed = encoder.transform_dataframe(&df).unwrap();
+        assert_eq!(transformed.column("city").unwrap().dtype(), &DataType::Float32);
+    }
+}
@@ -0,0 +1,699 @@
+//! Conversion of numpy arrays and pandas DataFrames with float, integer and string categories into encoder inputs.
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::Arc;
+
+use fnv::FnvHashMap;
+use ndarray::{Array1, Array2, ArrayView2, ArrayViewMut2, Axis};
+use numpy::{Element, IntoPyArray, PyArray1, PyArray2};
+use ordered_float::OrderedFloat;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyFloat, PyString};
+
+#[cfg(feature = "arrow")]
+use arrow_array::{ffi::{from_ffi, FFI_ArrowArray, FFI_ArrowSchema}, RecordBatch, StructArray};
+#[cfg(feature = "arrow")]
+use crate::arrow_input::ArrowKey;
+#[cfg(feature = "arrow")]
+use pyo3::AsPyPointer;
+use crate::folds::FoldSplitter;
+use crate::target_encoder::{CompactionReport, Data, Encoders, MatrixEncoder, RareGrouping};
+use crate::utils::as_ordered_float;
+
+/// Categorical data extracted from a numpy array or an Arrow record batch
+pub enum Categories<'py, T> {
+    /// Float and integer categories are views of the numpy array
+    Float(ArrayView2<'py, OrderedFloat<T>>),
+    Int(ArrayView2<'py, i64>),
+    Str(Array2<StrKey>),
+    #[cfg(feature = "arrow")]
+    Arrow(RecordBatch),
+}
+
+impl<T> Categories<'_, T> {
+    fn kind(&self) -> &'static str {
+        match self {
+            Categories::Float(_) => "float",
+            Categories::Int(_) => "integer",
+            Categories::Str(_) => "string",
+            #[cfg(feature = "arrow")]
+            Categories::Arrow(_) => "Arrow",
+        }
+    }
+
+    /// Memory borrowed from Python objects by the categories. String categories are always copied.
+    fn byte_ranges(&self) -> Vec<Range<usize>> {
+        fn view_range<A>(data: &ArrayView2<A>) -> Range<usize> {
+            let size = std::mem::size_of::<A>();
+            let strides: Vec<isize> = data.strides().iter().map(|&stride| stride * size as isize).collect();
+            byte_range(data.as_ptr() as usize, size, data.shape(), &strides)
+        }
+        match self {
+            Categories::Float(data) => vec![view_range(data)],
+            Categories::Int(data) => vec![view_range(data)],
+            Categories::Str(_) => vec![],
+            #[cfg(feature = "arrow")]
+            Categories::Arrow(batch) => {
+                fn data_ranges(data: &arrow_data::ArrayData, ranges: &mut Vec<Range<usize>>) {
+                    let buffers = data.buffers().iter().chain(data.nulls().map(|nulls| nulls.buffer()));
+                    ranges.extend(buffers.map(|buffer| buffer.as_ptr() as usize..buffer.as_ptr() as usize + buffer.len()));
+                    data.child_data().iter().for_each(|child| data_ranges(child, ranges));
+                }
+                let mut ranges = Vec::new();
+                batch.columns().iter().for_each(|column| data_ranges(&column.to_data(), &mut ranges));
+                ranges
+            },
+        }
+    }
+
+    fn dim(&self) -> (usize, usize) {
+        match self {
+            Categories::Float(data) => data.dim(),
+            Categories::Int(data) => data.dim(),
+            Categories::Str(data) => data.dim(),
+            #[cfg(feature = "arrow")]
+            Categories::Arrow(batch) => (batch.num_rows(), batch.num_columns()),
+        }
+    }
+}
+
+/// Encoder fitted on one of the supported category types
+pub enum KeyedEncoder<T: Data> {
+    Float(MatrixEncoder<OrderedFloat<T>, T, T>),
+    Int(MatrixEncoder<i64, T, T>),
+    Str(MatrixEncoder<StrKey, T, T>),
+    #[cfg(feature = "arrow")]
+    Arrow(MatrixEncoder<ArrowKey, T, T>),
+}
+
+/// Fit a [`KeyedEncoder`] on [`Categories`]. `$fit` is evaluated with `$data` bound to the typed categories
+/// and must return a tuple of the fitted `MatrixEncoder` and any additional output.
+/// Arrow record batches are converted to a 2d array of keys first, raising `ValueError` for unsupported columns.
+macro_rules! fit_categories {
+    ($categories:expr, |$data:ident| $fit:expr) => {
+        match $categories {
+            Categories::Float($data) => { let (encoder, out) = $fit; PyResult::Ok((KeyedEncoder::Float(encoder), out)) },
+            Categories::Int($data) => { let (encoder, out) = $fit; PyResult::Ok((KeyedEncoder::Int(encoder), out)) },
+            Categories::Str($data) => { let (encoder, out) = $fit; PyResult::Ok((KeyedEncoder::Str(encoder), out)) },
+            #[cfg(feature = "arrow")]
+            Categories::Arrow(batch) => match $crate::arrow_input::record_batch_keys(&batch) {
+                Ok($data) => { let (encoder, out) = $fit; Ok((KeyedEncoder::Arrow(encoder), out)) },
+                Err(error) => Err($crate::python::arrow_error(error)),
+            },
+        }
+    };
+}
+
+/// Evaluate `$body` with the encoder and categories of the same type, raising `TypeError` on a type mismatch
+macro_rules! with_same_keys {
+    ($encoder:expr, $categories:expr, |$e:ident, $data:ident| $body:expr) => {
+        match ($encoder, $categories) {
+            (KeyedEncoder::Float($e), Categories::Float($data)) => Ok($body),
+            (KeyedEncoder::Int($e), Categories::Int($data)) => Ok($body),
+            (KeyedEncoder::Str($e), Categories::Str($data)) => Ok($body),
+            #[cfg(feature = "arrow")]
+            (KeyedEncoder::Arrow($e), Categories::Arrow(batch)) => {
+                let $data = $crate::arrow_input::record_batch_keys(&batch).map_err($crate::python::arrow_error)?;
+                Ok($body)
+            },
+            (encoder, categories) => Err(PyTypeError::new_err(format!(
+                "Encoder was fitted on {} categories, but got {} categories", encoder.kind(), categories.kind()
+            ))),
+        }
+    };
+}
+
+impl<T: Data + Element + ToPyObject> KeyedEncoder<T> {
+    fn kind(&self) -> &'static str {
+        match self {
+            KeyedEncoder::Float(_) => "float",
+            KeyedEncoder::Int(_) => "integer",
+            KeyedEncoder::Str(_) => "string",
+            #[cfg(feature = "arrow")]
+            KeyedEncoder::Arrow(_) => "Arrow",
+        }
+    }
+
+    pub fn transform(&self, py: Python, categories: Categories<T>) -> PyResult<Array2<T>> {
+        #[cfg(feature = "arrow")]
+        if let (KeyedEncoder::Arrow(encoder), Categories::Arrow(batch)) = (self, &categories) {
+            let names: Vec<String> = batch.schema().fields().iter().map(|f| f.name().clone()).collect();
+            encoder.check_column_names(&names).map_err(PyValueError::new_err)?;
+            return py.allow_threads(|| encoder.transform_record_batch(batch)).map_err(arrow_error);
+        }
+
+        with_same_keys!(self, categories, |encoder, data| {
+            py.allow_threads(|| encoder.transform_to(data.view()))
+        })
+    }
+
+    /// Check that `out` has a row for each row of `categories` and a column for each output column, and that it
+    /// doesn't share memory with them. Must be called before taking a mutable view of `out`.
+    pub fn check_output(&self, categories: &Categories<T>, out: &PyArray2<T>) -> PyResult<()> {
+        let shape = (categories.dim().0, self.num_output_columns());
+        if out.shape() != [shape.0, shape.1] {
+            return Err(PyValueError::new_err(format!("Output has shape {:?}, but the encoded data has shape {:?}", out.shape(), shape)));
+        }
+        let out_range = byte_range(unsafe { (*out.as_array_ptr()).data } as usize, std::mem::size_of::<T>(), out.shape(), out.strides());
+        if categories.byte_ranges().iter().any(|range| range.start < out_range.end && out_range.start < range.end) {
+            return Err(PyValueError::new_err("`out` must not share memory with the data"));
+        }
+        Ok(())
+    }
+
+    /// Encode `categories` into the preallocated array `out`, checked with [`check_output`](KeyedEncoder::check_output)
+    pub fn transform_into(&self, py: Python, categories: Categories<T>, out: ArrayViewMut2<T>) -> PyResult<()> {
+        #[cfg(feature = "arrow")]
+        if let Categories::Arrow(_) = categories {
+            let mut out = out;
+            out.assign(&self.transform(py, categories)?);
+            return Ok(());
+        }
+
+        with_same_keys!(self, categories, |encoder, data| {
+            py.allow_threads(|| encoder.transform_into(data.view(), out))
+        })
+    }
+
+    pub fn partial_fit(&mut self, py: Python, categories: Categories<T>, target: ndarray::Array1<T>, timestamps: Option<ndarray::Array1<T>>) -> PyResult<()> {
+        with_same_keys!(self, categories, |encoder, data| {
+            py.allow_threads(|| match &timestamps {
+                Some(timestamps) => encoder.partial_fit_with_decay(&data, &target, timestamps),
+                None => encoder.partial_fit(&data, &target),
+            })
+        })
+    }
+
+    pub fn with_params(&self, py: Python, params: &Encoders<T>) -> KeyedEncoder<T> {
+        py.allow_threads(|| match self {
+            KeyedEncoder::Float(encoder) => KeyedEncoder::Float(encoder.with_params(params)),
+            KeyedEncoder::Int(encoder) => KeyedEncoder::Int(encoder.with_params(params)),
+            KeyedEncoder::Str(encoder) => KeyedEncoder::Str(encoder.with_params(params)),
+            #[cfg(feature = "arrow")]
+            KeyedEncoder::Arrow(encoder) => KeyedEncoder::Arrow(encoder.with_params(params)),
+        })
+    }
+
+    pub fn with_rare_grouping(self, rare_grouping: RareGrouping) -> KeyedEncoder<T> {
+        match self {
+            KeyedEncoder::Float(encoder) => KeyedEncoder::Float(encoder.with_rare_grouping(rare_grouping)),
+            KeyedEncoder::Int(encoder) => KeyedEncoder::Int(encoder.with_rare_grouping(rare_grouping)),
+            KeyedEncoder::Str(encoder) => KeyedEncoder::Str(encoder.with_rare_grouping(rare_grouping)),
+            #[cfg(feature = "arrow")]
+            KeyedEncoder::Arrow(encoder) => KeyedEncoder::Arrow(encoder.with_rare_grouping(rare_grouping)),
+        }
+    }
+
+    pub fn compact(&mut self, py: Python, min_count: usize, tolerance: T) -> CompactionReport {
+        py.allow_threads(|| match self {
+            KeyedEncoder::Float(encoder) => encoder.compact(min_count, tolerance),
+            KeyedEncoder::Int(encoder) => encoder.compact(min_count, tolerance),
+            KeyedEncoder::Str(encoder) => encoder.compact(min_count, tolerance),
+            #[cfg(feature = "arrow")]
+            KeyedEncoder::Arrow(encoder) => encoder.compact(min_count, tolerance),
+        })
+    }
+
+    pub fn with_column_names(self, names: Vec<String>) -> KeyedEncoder<T> {
+        match self {
+            KeyedEncoder::Float(encoder) => KeyedEncoder::Float(encoder.with_column_names(names)),
+            KeyedEncoder::Int(encoder) => KeyedEncoder::Int(encoder.with_column_names(names)),
+            KeyedEncoder::Str(encoder) => KeyedEncoder::Str(encoder.with_column_names(names)),
+            #[cfg(feature = "arrow")]
+            KeyedEncoder::Arrow(encoder) => KeyedEncoder::Arrow(encoder.with_column_names(names)),
+        }
+    }
+
+    pub fn num_output_columns(&self) -> usize {
+        match self {
+            KeyedEncoder::Float(encoder) => encoder.num_output_columns(),
+            KeyedEncoder::Int(encoder) => encoder.num_output_columns(),
+            KeyedEncoder::Str(encoder) => encoder.num_output_columns(),
+            #[cfg(feature = "arrow")]
+            KeyedEncoder::Arrow(encoder) => encoder.num_output_columns(),
+        }
+    }
+
+    pub fn check_column_names(&self, names: &[String]) -> PyResult<()> {
+        let checked = match self {
+            KeyedEncoder::Float(encoder) => encoder.check_column_names(names),
+            KeyedEncoder::Int(encoder) => encoder.check_column_names(names),
+            KeyedEncoder::Str(encoder) => encoder.check_column_names(names),
+            #[cfg(feature = "arrow")]
+            KeyedEncoder::Arrow(encoder) => encoder.check_column_names(names),
+        };
+        checked.map_err(PyValueError::new_err)
+    }
+
+    /// Fitted encodings as a list with a `{category: encoding}` dict for each column
+    pub fn mapping(&self, py: Python) -> PyResult<PyObject> {
+        fn column_dicts<K, T: Data + ToPyObject, P: ToPyObject>(py: Python, encoder: &MatrixEncoder<K, T, T>, key: impl Fn(&K) -> P) -> PyResult<PyObject>
+            where K: crate::target_encoder::Key {
+            let mut columns = Vec::with_capacity(encoder.columns().len());
+            for column in encoder.columns() {
+                let dict = PyDict::new(py);
+                for (k, encoding) in column.encodings() {
+                    dict.set_item(key(k), encoding)?;
+                }
+                columns.push(dict.to_object(py));
+            }
+            Ok(columns.to_object(py))
+        }
+
+        match self {
+            KeyedEncoder::Float(encoder) => column_dicts(py, encoder, |k| k.0),
+            KeyedEncoder::Int(encoder) => column_dicts(py, encoder, |k| *k),
+            KeyedEncoder::Str(encoder) => column_dicts(py, encoder, |k| k.as_deref().map(str::to_owned)),
+            #[cfg(feature = "arrow")]
+            KeyedEncoder::Arrow(encoder) => column_dicts(py, encoder, |k| match k {
+                ArrowKey::Null => py.None(),
+                ArrowKey::Int(i) => i.to_object(py),
+                ArrowKey::Str(s) => s.as_ref().to_object(py),
+            }),
+        }
+    }
+}
+
+/// Bytes spanned by an array of `shape` with `strides` in bytes whose first element of `size` bytes is at `address`
+fn byte_range(address: usize, size: usize, shape: &[usize], strides: &[isize]) -> Range<usize> {
+    if shape.contains(&0) {
+        return address..address;
+    }
+    let (low, high) = shape.iter().zip(strides).fold((0, 0), |(low, high), (&len, &stride)| {
+        let extent = (len as isize - 1) * stride;
+        (low + extent.min(0), high + extent.max(0))
+    });
+    (address as isize + low) as usize..(address as isize + high) as usize + size
+}
+
+/// String categories, `None` for missing values. Rows of the same category share the string.
+pub type StrKey = Option<Arc<str>>;
+
+/// Decode the distinct elements of the fixed-width buffer of a numpy unicode or bytes array once and share them between rows.
+/// Unicode elements are UCS-4 code points and bytes elements must be UTF-8, trailing NULs are padding.
+fn intern_fixed_width(buffer: &[u8], itemsize: usize, unicode: bool, num_elements: usize) -> Result<Vec<StrKey>, String> {
+    let mut interned: FnvHashMap<&[u8], StrKey> = FnvHashMap::default();
+    let mut keys = Vec::with_capacity(num_elements);
+    for i in 0..num_elements {
+        let element = &buffer[i * itemsize..(i + 1) * itemsize];
+        let len = element.iter().rposition(|&b| b != 0).map_or(0, |last| last + 1);
+        let element = if unicode { &element[..len.div_ceil(4) * 4] } else { &element[..len] };
+        if let Some(key) = interned.get(element) {
+            keys.push(key.clone());
+            continue;
+        }
+        let string = if unicode {
+            element.chunks_exact(4)
+                .map(|c| char::from_u32(u32::from_ne_bytes([c[0], c[1], c[2], c[3]])))
+                .collect::<Option<String>>()
+                .ok_or_else(|| "Unicode categories must be valid code points".to_string())?
+        } else {
+            std::str::from_utf8(element).map_err(|_| "Bytes categories must be UTF-8".to_string())?.to_owned()
+        };
+        let key = Some(Arc::from(string));
+        interned.insert(element, key.clone());
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+/// Category of an element of an object array: `None` for missing values (`None`, NaN and `pandas.NA`),
+/// strings and UTF-8 bytes as they are, and the string representation of other objects.
+/// The flag tells strings and bytes apart from the string representation of other objects.
+fn object_category(value: &PyAny) -> PyResult<Option<(Cow<'_, str>, bool)>> {
+    if value.is_none() || value.get_type().name()? == "NAType" {
+        return Ok(None);
+    }
+    if let Ok(value) = value.downcast::<PyFloat>() {
+        if value.value().is_nan() {
+            return Ok(None);
+        }
+    }
+    if let Ok(value) = value.downcast::<PyString>() {
+        return Ok(Some((Cow::Borrowed(value.to_str()?), true)));
+    }
+    if let Ok(value) = value.downcast::<PyBytes>() {
+        let value = std::str::from_utf8(value.as_bytes()).map_err(|_| PyValueError::new_err("Bytes categories must be UTF-8"))?;
+        return Ok(Some((Cow::Borrowed(value), true)));
+    }
+    Ok(Some((Cow::Owned(value.str()?.to_str()?.to_owned()), false)))
+}
+
+/// Reject columns of a row-major object array with both strings and other objects, which are encoded through their
+/// string representation, so that `1` and `"1"` are not silently the same category
+fn check_object_columns(values: &[Option<(Cow<str>, bool)>], num_columns: usize) -> PyResult<()> {
+    let mut kinds = vec![(false, false); num_columns];
+    for (i, value) in values.iter().enumerate() {
+        if let Some((_, is_string)) = value {
+            let kind = &mut kinds[i % num_columns];
+            if *is_string { kind.0 = true } else { kind.1 = true }
+        }
+    }
+    match kinds.iter().position(|&(strings, others)| strings && others) {
+        Some(j) => Err(PyValueError::new_err(format!(
+            "Object column {} mixes strings with other objects, convert it to a single type first", j
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Share equal strings between rows
+fn intern(values: &[Option<Cow<str>>]) -> Vec<StrKey> {
+    let mut interned: FnvHashMap<&str, Arc<str>> = FnvHashMap::default();
+    values.iter()
+        .map(|value| value.as_deref().map(|s| interned.entry(s).or_insert_with(|| Arc::from(s)).clone()))
+        .collect()
+}
+
+/// Extract a 2d array of categories from `data`. Float arrays are converted to `float_dtype`,
+/// integer and boolean arrays to `int64`, and unicode, bytes and object arrays to strings, with `None` for missing values.
+/// With the `arrow` feature, objects implementing `__arrow_c_array__` such as `pyarrow.RecordBatch` are imported without copying.
+pub fn extract_categories<'py, T: Data + Element>(py: Python<'py>, data: &'py PyAny, float_dtype: &str) -> PyResult<Categories<'py, T>> {
+    #[cfg(feature = "arrow")]
+    if data.hasattr("__arrow_c_array__")? {
+        return Ok(Categories::Arrow(extract_record_batch(py, data)?));
+    }
+
+    let np = py.import("numpy")?;
+    let array = np.call_method1("asarray", (data,))?;
+    let ndim: usize = array.getattr("ndim")?.extract()?;
+    if ndim != 2 {
+        return Err(PyTypeError::new_err(format!("Expected a 2d array of categories, got {} dimensions", ndim)));
+    }
+
+    let kind: String = array.getattr("dtype")?.getattr("kind")?.extract()?;
+    match kind.as_str() {
+        // arrays of the right dtype are not copied, the GIL is released while they are read
+        "f" => {
+            let array: &PyArray2<T> = np.call_method1("asarray", (array, float_dtype))?.extract()?;
+            Ok(Categories::Float(as_ordered_float(unsafe { array.as_array() })))
+        },
+        "i" | "u" | "b" => {
+            let array: &PyArray2<i64> = np.call_method1("asarray", (array, "int64"))?.extract()?;
+            Ok(Categories::Int(unsafe { array.as_array() }))
+        },
+        // strings are decoded and interned with the GIL released
+        "U" | "S" => {
+            let shape: (usize, usize) = array.getattr("shape")?.extract()?;
+            let dtype = array.getattr("dtype")?.call_method1("newbyteorder", ("=",))?;
+            let itemsize: usize = dtype.getattr("itemsize")?.extract()?;
+            let buffer: &PyArray1<u8> = np.call_method1("ascontiguousarray", (array, dtype))?
+                .call_method1("reshape", (-1,))?
+                .call_method1("view", ("uint8",))?
+                .extract()?;
+            let buffer = unsafe { buffer.as_slice() }.expect("Buffer of a contiguous array must be contiguous");
+            let num_elements = shape.0 * shape.1;
+            let unicode = kind == "U";
+            let keys = py.allow_threads(|| match itemsize {
+                0 => Ok(vec![Some(Arc::from("")); num_elements]),
+                _ => intern_fixed_width(buffer, itemsize, unicode, num_elements),
+            }).map_err(PyValueError::new_err)?;
+            Ok(Categories::Str(Array2::from_shape_vec(shape, keys).expect("Number of categories must match the array shape")))
+        },
+        "O" => {
+            let shape: (usize, usize) = array.getattr("shape")?.extract()?;
+            let objects = np.call_method1("ascontiguousarray", (array,))?;
+            let (address, _): (usize, bool) = objects.getattr("__array_interface__")?.get_item("data")?.extract()?;
+            // the contiguous buffer of an object array holds a pointer to each element, borrowed while `objects` lives
+            let pointers = match shape.0 * shape.1 {
+                0 => &[][..],
+                len => unsafe { std::slice::from_raw_parts(address as *const *mut pyo3::ffi::PyObject, len) },
+            };
+            let values = pointers.iter()
+                .map(|&pointer| object_category(unsafe { py.from_borrowed_ptr(pointer) }))
+                .collect::<PyResult<Vec<_>>>()?;
+            check_object_columns(&values, shape.1)?;
+            let values = values.into_iter().map(|value| value.map(|(category, _)| category)).collect::<Vec<_>>();
+            let keys = py.allow_threads(|| intern(&values));
+            Ok(Categories::Str(Array2::from_shape_vec(shape, keys).expect("Number of categories must match the array shape")))
+        },
+        _ => Err(PyTypeError::new_err(format!("Unsupported dtype kind '{}' for categories", kind))),
+    }
+}
+
+/// Groups of `fit_transform_oof` as a single column of categories. Any 1d array of categories accepted as data can be used.
+pub fn extract_groups<'py, T: Data + Element>(py: Python<'py>, groups: &'py PyAny, float_dtype: &str) -> PyResult<Categories<'py, T>> {
+    // converted to numpy first, so that Arrow arrays are not mistaken for record batches
+    let groups = py.import("numpy")?.call_method1("asarray", (groups,))?;
+    let ndim: usize = groups.getattr("ndim")?.extract()?;
+    if ndim != 1 {
+        return Err(PyTypeError::new_err(format!("Expected a 1d array of groups, got {} dimensions", ndim)));
+    }
+    extract_categories(py, groups.call_method1("reshape", (-1, 1))?, float_dtype)
+}
+
+/// Fold of each row with all rows of a group in the same fold, see [`FoldSplitter::group_kfold`]
+pub fn group_folds<T: Data>(splitter: &FoldSplitter, groups: Categories<T>) -> Array1<usize> {
+    match groups {
+        Categories::Float(groups) => splitter.group_kfold(&groups.column(0).to_owned()),
+        Categories::Int(groups) => splitter.group_kfold(&groups.column(0).to_owned()),
+        Categories::Str(groups) => splitter.group_kfold(&groups.column(0).to_owned()),
+        #[cfg(feature = "arrow")]
+        Categories::Arrow(_) => unreachable!("Groups are extracted from numpy arrays"),
+    }
+}
+
+/// `ValueError` for unsupported Arrow columns and batches with other columns than the fitted ones
+#[cfg(feature = "arrow")]
+pub fn arrow_error(error: arrow_schema::ArrowError) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+/// Import a record batch through the Arrow PyCapsule interface. Buffers are shared with the Python object.
+#[cfg(feature = "arrow")]
+fn extract_record_batch(py: Python, data: &PyAny) -> PyResult<RecordBatch> {
+    let (schema, array): (&PyAny, &PyAny) = data.call_method0("__arrow_c_array__")?.extract()?;
+    let array_data = unsafe {
+        let schema = pyo3::ffi::PyCapsule_GetPointer(schema.as_ptr(), b"arrow_schema\0".as_ptr() as *const _) as *const FFI_ArrowSchema;
+        let array = pyo3::ffi::PyCapsule_GetPointer(array.as_ptr(), b"arrow_array\0".as_ptr() as *const _) as *mut FFI_ArrowArray;
+        if schema.is_null() || array.is_null() {
+            return Err(PyErr::fetch(py));
+        }
+        // take ownership of the array, the capsule is left with a released one
+        from_ffi(FFI_ArrowArray::from_raw(array), &*schema).map_err(|e| PyValueError::new_err(e.to_string()))?
+    };
+
+    match array_data.data_type() {
+        arrow_schema::DataType::Struct(_) if array_data.null_count() == 0 => Ok(RecordBatch::from(StructArray::from(array_data))),
+        data_type => Err(PyTypeError::new_err(format!("Expected an Arrow record batch, got an array of type {}", data_type))),
+    }
+}
+
+/// Columns of the pandas DataFrame an encoder was fitted on
+#[derive(Debug, Clone)]
+pub struct FrameColumns {
+    /// Names of all columns of the DataFrame, in order
+    pub input: Vec<String>,
+    /// Positions of the encoded columns in `input`, other columns are passed through unchanged
+    pub encoded: Vec<usize>,
+}
+
+impl FrameColumns {
+    pub fn encoded_names(&self) -> Vec<String> {
+        self.encoded.iter().map(|&i| self.input[i].clone()).collect()
+    }
+}
+
+pub fn is_dataframe(py: Python, data: &PyAny) -> PyResult<bool> {
+    let pandas = match py.import("pandas") {
+        Ok(pandas) => pandas,
+        Err(_) => return Ok(false),
+    };
+    py.import("builtins")?.call1("isinstance", (data, pandas.getattr("DataFrame")?))?.extract()
+}
+
+fn column_names(data: &PyAny) -> PyResult<Vec<String>> {
+    data.getattr("columns")?
+        .iter()?
+        .map(|c| Ok(c?.str()?.to_str()?.to_owned()))
+        .collect()
+}
+
+/// Select columns of a DataFrame to encode by name, or by dtype with `DataFrame.select_dtypes`.
+/// Without either, object, category and string columns are encoded.
+pub fn select_columns(py: Python, data: &PyAny, columns: Option<Vec<String>>, dtypes: Option<&PyAny>) -> PyResult<FrameColumns> {
+    let input = column_names(data)?;
+    let selected = match columns {
+        Some(columns) => columns,
+        None => {
+            let include = match dtypes {
+                Some(dtypes) => dtypes.to_object(py),
+                None => vec!["object", "category", "string"].to_object(py),
+            };
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("include", include)?;
+            column_names(data.call_method("select_dtypes", (), Some(kwargs))?)?
+        }
+    };
+    if selected.is_empty() {
+        return Err(PyValueError::new_err("No columns selected for encoding"));
+    }
+
+    let encoded = selected.iter()
+        .map(|name| input.iter().position(|c| c == name)
+            .ok_or_else(|| PyValueError::new_err(format!("Column {:?} is not in the DataFrame", name))))
+        .collect::<PyResult<Vec<usize>>>()?;
+    Ok(FrameColumns { input, encoded })
+}
+
+/// Extract the encoded columns of a DataFrame, checking that it has the same columns as the fitted one
+pub fn frame_categories<'py, T: Data + Element>(py: Python<'py>, data: &'py PyAny, frame: &FrameColumns, float_dtype: &str) -> PyResult<Categories<'py, T>> {
+    let input = column_names(data)?;
+    if input != frame.input {
+        return Err(PyValueError::new_err(format!(
+            "Encoder was fitted on a DataFrame with columns {:?}, but got columns {:?}", frame.input, input
+        )));
+    }
+
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("axis", 1)?;
+    let selected = data.call_method("take", (frame.encoded.clone(),), Some(kwargs))?;
+    extract_categories(py, selected.call_method0("to_numpy")?, float_dtype)
+}
+
+/// Copy of the DataFrame `data` with the encoded columns replaced by `encoded`. The index and other columns are kept.
+pub fn frame_output<T: Data + Element>(py: Python, data: &PyAny, frame: &FrameColumns, encoded: Array2<T>) -> PyResult<PyObject> {
+    let output = data.call_method0("copy")?;
+    let labels = data.getattr("columns")?;
+    for (column, &position) in encoded.axis_iter(Axis(1)).zip(frame.encoded.iter()) {
+        let label = labels.get_item(position)?;
+        output.set_item(label, column.to_owned().into_pyarray(py))?;
+    }
+    Ok(output.to_object(py))
+}
+
+#[cfg(feature = "polars")]
+pub fn is_polars_frame(py: Python, data: &PyAny) -> PyResult<bool> {
+    let polars = match py.import("polars") {
+        Ok(polars) => polars,
+        Err(_) => return Ok(false),
+    };
+    py.import("builtins")?.call1("isinstance", (data, polars.getattr("DataFrame")?))?.extract()
+}
+
+/// Select columns of a polars DataFrame to encode by name, or by dtype with `polars.selectors.by_dtype`.
+/// Without either, String and Categorical columns are encoded.
+#[cfg(feature = "polars")]
+fn select_polars_columns(py: Python, data: &PyAny, columns: Option<Vec<String>>, dtypes: Option<&PyAny>) -> PyResult<FrameColumns> {
+    let input: Vec<String> = data.getattr("columns")?.extract()?;
+    let selected = match columns {
+        Some(columns) => columns,
+        None => {
+            let selectors = py.import("polars.selectors")?;
+            let selector = match dtypes {
+                Some(dtypes) => selectors.call1("by_dtype", (dtypes,))?,
+                None => selectors.call0("string")?.call_method1("__or__", (selectors.call0("categorical")?,))?,
+            };
+            data.call_method1("select", (selector,))?.getattr("columns")?.extract()?
+        }
+    };
+    if selected.is_empty() {
+        return Err(PyValueError::new_err("No columns selected for encoding"));
+    }
+
+    let encoded = selected.iter()
+        .map(|name| input.iter().position(|c| c == name)
+            .ok_or_else(|| PyValueError::new_err(format!("Column {:?} is not in the DataFrame", name))))
+        .collect::<PyResult<Vec<usize>>>()?;
+    Ok(FrameColumns { input, encoded })
+}
+
+/// Extract the encoded columns of a polars DataFrame as an Arrow record batch,
+/// checking that it has the same columns as the fitted one
+#[cfg(feature = "polars")]
+fn polars_categories<'py, T: Data + Element>(py: Python<'py>, data: &'py PyAny, frame: &FrameColumns) -> PyResult<Categories<'py, T>> {
+    let input: Vec<String> = data.getattr("columns")?.extract()?;
+    if input != frame.input {
+        return Err(PyValueError::new_err(format!(
+            "Encoder was fitted on a DataFrame with columns {:?}, but got columns {:?}", frame.input, input
+        )));
+    }
+
+    // a single chunk per column gives a single record batch, or none for an empty DataFrame
+    let table = data.call_method1("select", (frame.encoded_names(),))?
+        .call_method0("rechunk")?
+        .call_method0("to_arrow")?
+        .call_method0("combine_chunks")?;
+    let batches: Vec<&PyAny> = table.call_method0("to_batches")?.extract()?;
+    match batches.as_slice() {
+        [] => {
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("schema", table.getattr("schema")?)?;
+            let empty = py.import("pyarrow")?.getattr("RecordBatch")?.call_method("from_pylist", (Vec::<PyObject>::new(),), Some(kwargs))?;
+            Ok(Categories::Arrow(extract_record_batch(py, empty)?))
+        },
+        [batch] => Ok(Categories::Arrow(extract_record_batch(py, batch)?)),
+        batches => Err(PyValueError::new_err(format!("DataFrame was converted into {} Arrow record batches instead of one", batches.len()))),
+    }
+}
+
+/// Copy of the polars DataFrame `data` with the encoded columns replaced by `encoded`
+#[cfg(feature = "polars")]
+fn polars_output<T: Data + Element>(py: Python, data: &PyAny, frame: &FrameColumns, encoded: Array2<T>) -> PyResult<PyObject> {
+    let polars = py.import("polars")?;
+    let columns = encoded.axis_iter(Axis(1))
+        .zip(frame.encoded_names())
+        .map(|(column, name)| polars.call1("Series", (name, column.to_owned().into_pyarray(py))))
+        .collect::<PyResult<Vec<&PyAny>>>()?;
+    Ok(data.call_method1("with_columns", (columns,))?.to_object(py))
+}
+
+/// Categories for fitting: the selected columns of a pandas or polars DataFrame, or a whole array
+pub fn fit_input<'py, T: Data + Element>(
+    py: Python<'py>,
+    data: &'py PyAny,
+    columns: Option<Vec<String>>,
+    dtypes: Option<&PyAny>,
+    float_dtype: &str,
+) -> PyResult<(Option<FrameColumns>, Categories<'py, T>)> {
+    if is_dataframe(py, data)? {
+        let frame = select_columns(py, data, columns, dtypes)?;
+        let categories = frame_categories(py, data, &frame, float_dtype)?;
+        return Ok((Some(frame), categories));
+    }
+    #[cfg(feature = "polars")]
+    if is_polars_frame(py, data)? {
+        let frame = select_polars_columns(py, data, columns, dtypes)?;
+        let categories = polars_categories(py, data, &frame)?;
+        return Ok((Some(frame), categories));
+    }
+
+    if columns.is_some() || dtypes.is_some() {
+        Err(PyTypeError::new_err("`columns` and `dtypes` can only be used with DataFrames"))
+    } else {
+        Ok((None, extract_categories(py, data, float_dtype)?))
+    }
+}
+
+/// Categories for transform. DataFrames are validated against the columns of the fitted DataFrame.
+pub fn transform_input<'py, T: Data + Element + ToPyObject>(
+    py: Python<'py>,
+    data: &'py PyAny,
+    encoder: &KeyedEncoder<T>,
+    frame: Option<&FrameColumns>,
+    float_dtype: &str,
+) -> PyResult<Categories<'py, T>> {
+    match frame {
+        Some(frame) if is_dataframe(py, data)? => {
+            encoder.check_column_names(&frame.encoded_names())?;
+            frame_categories(py, data, frame, float_dtype)
+        },
+        #[cfg(feature = "polars")]
+        Some(frame) if is_polars_frame(py, data)? => {
+            encoder.check_column_names(&frame.encoded_names())?;
+            polars_categories(py, data, frame)
+        },
+        _ => extract_categories(py, data, float_dtype),
+    }
+}
+
+/// Encoded data in the same form as the input: a DataFrame of the same library for DataFrames, otherwise a numpy array
+pub fn output<T: Data + Element>(py: Python, data: &PyAny, frame: Option<&FrameColumns>, encoded: Array2<T>) -> PyResult<PyObject> {
+    match frame {
+        Some(frame) if is_dataframe(py, data)? => frame_output(py, data, frame, encoded),
+        #[cfg(feature = "polars")]
+        Some(frame) if is_polars_frame(py, data)? => polars_output(py, data, frame, encoded),
+        _ => Ok(encoded.into_pyarray(py).to_object(py)),
+    }
+}
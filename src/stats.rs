@@ -1,5 +1,5 @@
 use num_traits::{Float, FromPrimitive};
-use std::iter::Sum;
+use std::iter::{FromIterator, Sum};
 use ndarray::{Array1};
 
 pub trait SummaryStatistics<T: Float + Sum + FromPrimitive> {
@@ -17,16 +17,7 @@ impl<T: Float + Sum + FromPrimitive> SummaryStatistics<T> for Array1<T> {
             "`ddof` must not be less than zero or greater than the length of \
              the axis",
         );
-        let dof = n - ddof;
-        let mut mean = T::zero();
-        let mut sum_sq = T::zero();
-        for (i, &x) in self.into_iter().enumerate() {
-            let count = T::from_usize(i + 1).expect("Converting index to `T` must not fail.");
-            let delta = x - mean;
-            mean = mean + delta / count;
-            sum_sq = (x - mean).mul_add(delta, sum_sq);
-        }
-        sum_sq / dof
+        TargetStats::from_iter(self.iter().copied()).var(ddof)
     }
 
     fn std(&self, ddof: T) -> T { 
@@ -34,6 +25,95 @@ impl<T: Float + Sum + FromPrimitive> SummaryStatistics<T> for Array1<T> {
      }
 }
 
+/// Sufficient statistics of a sample of target values: the number of observations,
+/// their (weighted) count, mean and sum of squared deviations from the mean.
+/// Encodings can be recomputed from these values without going back to the raw data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetStats<T> {
+    /// Number of observations
+    pub count: usize,
+    /// Sum of observation weights, equal to `count` for unweighted data
+    pub weight: T,
+    pub mean: T,
+    /// Sum of squared deviations from the mean
+    pub m2: T,
+}
+
+impl<T: Float + FromPrimitive> TargetStats<T> {
+    pub fn new() -> TargetStats<T> {
+        TargetStats { count: 0, weight: T::zero(), mean: T::zero(), m2: T::zero() }
+    }
+
+    /// Add a single observation using Welford's online algorithm
+    pub fn push(&mut self, x: T) {
+        self.push_weighted(x, T::one());
+    }
+
+    /// Add a single observation with weight `w` using the weighted version of Welford's algorithm
+    pub fn push_weighted(&mut self, x: T, w: T) {
+        self.count += 1;
+        self.weight = self.weight + w;
+        let delta = x - self.mean;
+        self.mean = self.mean + delta * w / self.weight;
+        self.m2 = (w * (x - self.mean)).mul_add(delta, self.m2);
+    }
+
+    /// Scale weights of all observations by `factor`, e.g. to decay old observations over time.
+    /// The mean is not affected.
+    pub fn decay(&mut self, factor: T) {
+        self.weight = self.weight * factor;
+        self.m2 = self.m2 * factor;
+    }
+
+    /// Combine statistics of two disjoint samples using Chan's parallel algorithm, so that the result is
+    /// the same as if all observations were pushed into a single `TargetStats`
+    pub fn merge(&mut self, other: &TargetStats<T>) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+
+        let weight = self.weight + other.weight;
+        let delta = other.mean - self.mean;
+        self.mean = self.mean + delta * other.weight / weight;
+        self.m2 = self.m2 + other.m2 + delta * delta * self.weight * other.weight / weight;
+        self.weight = weight;
+        self.count += other.count;
+    }
+
+    pub fn sum(&self) -> T {
+        self.mean * self.weight
+    }
+
+    /// Variance with `ddof` delta degrees of freedom. Returns NaN when `weight <= ddof`.
+    pub fn var(&self, ddof: T) -> T {
+        if self.weight > ddof {
+            self.m2 / (self.weight - ddof)
+        } else {
+            T::nan()
+        }
+    }
+}
+
+impl<T: Float + FromPrimitive> FromIterator<T> for TargetStats<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> TargetStats<T> {
+        let mut stats = TargetStats::new();
+        for x in values {
+            stats.push(x);
+        }
+        stats
+    }
+}
+
+impl<T: Float + FromPrimitive> Default for TargetStats<T> {
+    fn default() -> Self {
+        TargetStats::new()
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -60,4 +140,47 @@ mod tests {
         let stddev = a.std(1.);
        assert_abs_diff_eq!(stddev, 2.59483, epsilon = 1e-4);
     }
+
+    #[test]
+    fn test_target_stats() {
+        let stats = TargetStats::from_iter(vec![1., -4.32, 1.14, 0.32]);
+        assert_eq!(stats.count, 4);
+        assert_abs_diff_eq!(stats.sum(), -1.86, epsilon = 1e-8);
+        assert_abs_diff_eq!(stats.mean, -0.465, epsilon = 1e-8);
+        assert_abs_diff_eq!(stats.var(1.), 6.7331, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn test_target_stats_merge() {
+        let mut left = TargetStats::from_iter(vec![1., -4.32]);
+        let right = TargetStats::from_iter(vec![1.14, 0.32, 2.5]);
+        left.merge(&right);
+        left.merge(&TargetStats::new());
+
+        let expected = TargetStats::from_iter(vec![1., -4.32, 1.14, 0.32, 2.5]);
+        assert_eq!(left.count, expected.count);
+        assert_abs_diff_eq!(left.weight, expected.weight, epsilon = 1e-12);
+        assert_abs_diff_eq!(left.mean, expected.mean, epsilon = 1e-12);
+        assert_abs_diff_eq!(left.m2, expected.m2, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_target_stats_weighted() {
+        // integer weights are the same as repeating observations
+        let mut weighted = TargetStats::new();
+        weighted.push_weighted(1., 2.);
+        weighted.push_weighted(-4.32, 1.);
+        weighted.push_weighted(0.32, 3.);
+
+        let expected = TargetStats::from_iter(vec![1., 1., -4.32, 0.32, 0.32, 0.32]);
+        assert_eq!(weighted.count, 3);
+        assert_abs_diff_eq!(weighted.weight, expected.weight, epsilon = 1e-12);
+        assert_abs_diff_eq!(weighted.mean, expected.mean, epsilon = 1e-12);
+        assert_abs_diff_eq!(weighted.var(1.), expected.var(1.), epsilon = 1e-12);
+
+        weighted.decay(0.5);
+        assert_abs_diff_eq!(weighted.weight, 3., epsilon = 1e-12);
+        assert_abs_diff_eq!(weighted.mean, expected.mean, epsilon = 1e-12);
+        assert_abs_diff_eq!(weighted.var(0.), expected.var(0.), epsilon = 1e-12);
+    }
 }
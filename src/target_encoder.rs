@@ -1,23 +1,262 @@
 #![feature(trait_alias)]
 
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
 use std::iter::Sum;
-use std::marker::PhantomData;
+use std::mem::size_of;
+use std::sync::{Arc, OnceLock};
 
-use fnv::FnvHashMap;
+#[cfg(feature = "frozen")]
+use bytemuck::Pod;
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
 use itertools::Itertools;
 use ndarray::parallel::prelude::*;
 use ndarray::prelude::*;
+use ndarray::Zip;
 use num_traits::{Float, FromPrimitive, ToPrimitive};
 use ordered_float::OrderedFloat;
 use rayon::prelude::*;
 
-use crate::stats::SummaryStatistics;
-use crate::utils::ToOrderedFloat;
+#[cfg(feature = "frozen")]
+use crate::frozen::{params_f64, FrozenColumn, FrozenEncoder};
+use crate::interactions::{CompositeKey, Interaction, Interactions};
+use crate::stats::TargetStats;
+use crate::utils::{as_ordered_float, ToOrderedFloat};
 use std::fmt::Debug;
 use num_traits::real::Real;
 use std::fmt::Display;
 
-pub trait Data = Float + FromPrimitive + Sum + ToPrimitive + Sync + Send + Display;
+pub trait Data = Float + FromPrimitive + Sum + ToPrimitive + Sync + Send + Display + Debug;
+/// Category keys. Integers and strings can be used directly, floats should be wrapped in [`OrderedFloat`].
+/// Encoding also requires [`DenseKey`], passing columns through [`NumericKey`] and freezing [`FrozenKey`].
+pub trait Key = Hash + Eq + Ord + Clone + Send + Sync + Debug;
+
+/// Keys that can be small non-negative integer codes, so that encodings can be stored in a `Vec` indexed by code
+/// instead of a hash map. See [`DenseMode`].
+pub trait DenseKey {
+    /// Code of this key, `None` if it is not a non-negative integer
+    fn dense_code(&self) -> Option<usize> {
+        None
+    }
+}
+
+macro_rules! impl_dense_key_for_int {
+    ($($t:ty),*) => {
+        $(impl DenseKey for $t {
+            fn dense_code(&self) -> Option<usize> {
+                if *self >= 0 as $t && (*self as u64) <= usize::MAX as u64 { Some(*self as usize) } else { None }
+            }
+        })*
+    };
+}
+
+impl_dense_key_for_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<F: Float> DenseKey for OrderedFloat<F> {
+    fn dense_code(&self) -> Option<usize> {
+        if self.0 >= F::zero() && self.0.fract() == F::zero() { self.0.to_usize() } else { None }
+    }
+}
+
+impl DenseKey for String {}
+impl DenseKey for &str {}
+impl DenseKey for Option<Arc<str>> {}
+impl DenseKey for CompositeKey {}
+
+/// Keys with a numeric value, so that columns which are not encoded can be passed through to the output.
+/// See [`Remainder`].
+pub trait NumericKey {
+    /// Value of this key in the output type, `None` if it is not numeric
+    fn numeric_value<O: Float>(&self) -> Option<O> {
+        None
+    }
+}
+
+macro_rules! impl_numeric_key_for_int {
+    ($($t:ty),*) => {
+        $(impl NumericKey for $t {
+            fn numeric_value<O: Float>(&self) -> Option<O> {
+                O::from(*self)
+            }
+        })*
+    };
+}
+
+impl_numeric_key_for_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<F: Float> NumericKey for OrderedFloat<F> {
+    fn numeric_value<O: Float>(&self) -> Option<O> {
+        O::from(self.0)
+    }
+}
+
+impl NumericKey for String {}
+impl NumericKey for &str {}
+impl NumericKey for Option<Arc<str>> {}
+
+/// Keys that can be stored in the flat sorted arrays of a [`FrozenColumn`](crate::frozen::FrozenColumn)
+pub trait FrozenKey {
+    /// Kind of keys in [model files](crate::model_file). Keys of the same kind have the same search keys and bytes.
+    const KEY_KIND: u64;
+
+    /// Fixed-width key the arrays are sorted and searched by. Equal keys must have equal search keys.
+    fn search_key(&self) -> u64;
+
+    /// Bytes telling apart keys with the same search key, `None` if the search key identifies the key
+    fn key_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        None
+    }
+}
+
+/// Search key of variable-length keys, stable across processes so that it can be stored in model files
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+pub(crate) const SIGNED_KEYS: u64 = 1;
+pub(crate) const UNSIGNED_KEYS: u64 = 2;
+pub(crate) const FLOAT_KEYS: u64 = 3;
+pub(crate) const STRING_KEYS: u64 = 4;
+pub(crate) const OPTIONAL_STRING_KEYS: u64 = 6;
+
+macro_rules! impl_frozen_key_for_int {
+    ($kind:expr, $($t:ty),*) => {
+        $(impl FrozenKey for $t {
+            const KEY_KIND: u64 = $kind;
+
+            fn search_key(&self) -> u64 {
+                // signed keys are sign-extended, so that the same value has the same search key in every width
+                *self as u64
+            }
+        })*
+    };
+}
+
+impl_frozen_key_for_int!(SIGNED_KEYS, i8, i16, i32, i64, isize);
+impl_frozen_key_for_int!(UNSIGNED_KEYS, u8, u16, u32, u64, usize);
+
+impl<F: Float> FrozenKey for OrderedFloat<F> {
+    const KEY_KIND: u64 = FLOAT_KEYS;
+
+    fn search_key(&self) -> u64 {
+        // all NaNs and both zeros are equal keys
+        match self.0.to_f64().unwrap() {
+            x if x.is_nan() => f64::NAN.to_bits(),
+            0. => 0,
+            x => x.to_bits(),
+        }
+    }
+}
+
+impl FrozenKey for String {
+    const KEY_KIND: u64 = STRING_KEYS;
+
+    fn search_key(&self) -> u64 {
+        hash_bytes(self.as_bytes())
+    }
+
+    fn key_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        Some(Cow::Borrowed(self.as_bytes()))
+    }
+}
+
+impl FrozenKey for &str {
+    const KEY_KIND: u64 = STRING_KEYS;
+
+    fn search_key(&self) -> u64 {
+        hash_bytes(self.as_bytes())
+    }
+
+    fn key_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        Some(Cow::Borrowed(self.as_bytes()))
+    }
+}
+
+/// Bytes of a missing string, which are not valid UTF-8 and differ from the bytes of every string
+const MISSING_STRING_BYTES: &[u8] = &[0xff];
+
+/// Strings with `None` for missing values, as extracted from Python string and object arrays
+impl FrozenKey for Option<Arc<str>> {
+    const KEY_KIND: u64 = OPTIONAL_STRING_KEYS;
+
+    fn search_key(&self) -> u64 {
+        hash_bytes(&self.key_bytes().unwrap())
+    }
+
+    fn key_bytes(&self) -> Option<Cow<'_, [u8]>> {
+        Some(Cow::Borrowed(self.as_deref().map_or(MISSING_STRING_BYTES, str::as_bytes)))
+    }
+}
+
+/// When to store the encodings of a column in a dense `Vec` indexed by integer code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenseMode {
+    /// Use a dense table when all categories have codes and the largest code is small compared to the number of categories
+    Auto,
+    /// Use a dense table whenever all categories have codes, unless the largest code is 2^24 or more
+    Always,
+    /// Always use the hash map
+    Never,
+}
+
+/// What happens to the columns that are not encoded by a [`MatrixEncoder`] fitted with
+/// [`fit_columns`](MatrixEncoder::fit_columns)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Remainder {
+    /// Keep them unchanged at their position in the output
+    Passthrough,
+    /// Leave them out, the output only has the encoded columns
+    Drop,
+}
+
+/// Folding of infrequent categories into a shared "other" category with statistics of its own,
+/// see [`ColumnTargetEncoder::with_rare_grouping`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RareGrouping {
+    /// Categories with fewer rows are folded
+    pub min_frequency: usize,
+    /// Only this many of the most frequent categories are kept, the others are folded
+    pub max_categories: Option<usize>,
+}
+
+/// Size of a [`MatrixEncoder`] before and after [`compact`](MatrixEncoder::compact)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Number of categories of all columns and interactions
+    pub categories_before: usize,
+    pub categories_after: usize,
+    /// Approximate memory used by the encoder, see [`memory_size`](MatrixEncoder::memory_size)
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+/// Source of a column of the output of [`MatrixEncoder::transform_to`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum OutputColumn {
+    /// Encoded by the column encoder with this index
+    Encoded(usize),
+    /// Input column with this index, passed through
+    Passthrough(usize),
+}
+
+/// Number of rows sampled to estimate the cardinality of a column before choosing how to aggregate it
+const AGGREGATION_SAMPLE_SIZE: usize = 1024;
+/// Hash aggregation is used if the sample has at least this many rows per distinct category
+const HASH_AGGREGATION_MAX_RATIO: usize = 2;
+/// Minimal number of rows aggregated into one partial hash map
+const HASH_AGGREGATION_CHUNK: usize = 64 * 1024;
+
+/// Number of rows encoded by one parallel task in `transform`
+const TRANSFORM_ROW_CHUNK: usize = 16 * 1024;
+
+/// Dense tables are used in [`DenseMode::Auto`] if they have at most this many entries per category...
+const DENSE_MAX_RATIO: usize = 4;
+/// ...or at most this many entries in total
+const DENSE_MIN_LEN: usize = 1024;
+/// Dense tables never have more entries than this, even in [`DenseMode::Always`]
+const DENSE_MAX_LEN: usize = 1 << 24;
 // pub trait Encoder<T>
 // where
 //     T: Float + FromPrimitive,
@@ -25,41 +264,69 @@ pub trait Data = Float + FromPrimitive + Sum + ToPrimitive + Sync + Send + Displ
 //     fn compute_encoding(&self) -> T;
 // }
 
-/// Target encoding for multiple columns
-pub struct MatrixEncoder<D, T>
+/// Target encoding for multiple columns with categories of type `K`
+pub struct MatrixEncoder<K, D, T>
 where
-    D: Float,
+    D: Float + FromPrimitive,
     T: Float,
 {
-    encodings: Vec<ColumnTargetEncoder<D, T>>,
-    phantom_target: PhantomData<T>,
+    params: Encoders<D>,
+    target_stats: TargetStats<D>,
+    decay: Option<TimeDecay<D>>,
+    column_names: Option<Vec<String>>,
+    /// Input column of each column encoder
+    input_columns: Vec<usize>,
+    num_input_columns: usize,
+    remainder: Remainder,
+    encodings: Vec<ColumnTargetEncoder<K, D, T>>,
+    interactions: Interactions<K, D, T>,
+}
+
+/// Exponential decay of row weights over time.
+/// A row that is `half_life` older than the most recent row counts half as much when computing encodings.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeDecay<T> {
+    pub half_life: T,
+    /// The most recent timestamp seen so far, row weights are computed relative to it
+    pub reference_time: T,
+}
+
+impl<T: Data> TimeDecay<T> {
+    /// Weight of a row observed at `timestamp`
+    pub fn weight(&self, timestamp: T) -> T {
+        let ln_2 = T::from_f64(std::f64::consts::LN_2).unwrap();
+        (-(self.reference_time - timestamp) * ln_2 / self.half_life).exp()
+    }
 }
 
 /// Target encoding on single column.
 ///
 /// The research paper describing the algorithm can be found here: [A preprocessing scheme for high-cardinality categorical attributes in classification and prediction problems](https://dl.acm.org/doi/10.1145/507533.507538)
-pub struct ColumnTargetEncoder<D, T>
+pub struct ColumnTargetEncoder<K, D, T>
 where
-    D: Float,
+    D: Float + FromPrimitive,
     T: Float,
 {
-    encodings: FnvHashMap<OrderedFloat<D>, OrderedFloat<D>>,
-    phantom_target: PhantomData<T>
-}
-
-pub struct TargetStats<T: Float + FromPrimitive> {
-    prior: T
+    params: Encoders<D>,
+    stats: FnvHashMap<K, TargetStats<D>>,
+    dense_mode: DenseMode,
+    min_count: usize,
+    rare_grouping: Option<RareGrouping>,
+    /// Statistics of the categories folded by `rare_grouping`
+    rare_stats: TargetStats<D>,
+    encodings: OnceLock<Encodings<K, T>>,
 }
 
-impl<T: Data> TargetStats<T> {
-    fn new(target: &Array1<T>) -> TargetStats<T> {
-        let prior = target.sum() / T::from_usize(target.len()).unwrap();
-        TargetStats { prior }
-    }
+/// Encodings of all categories in the output type, optionally also as a dense table indexed by category code,
+/// which is built on first use. Codes without a category hold the encoding of unknown categories.
+struct Encodings<K, T> {
+    map: FnvHashMap<K, T>,
+    dense: OnceLock<Option<Vec<T>>>,
+    unknown: T,
 }
 
 #[derive(Debug)]
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub enum Encoders<T>
 where
     T: Float + FromPrimitive,
@@ -78,6 +345,18 @@ where
         global_target_var: Option<T>,
         global_target_count: Option<usize>
     },
+    /// Target encoding with empirical-Bayes smoothing estimated from the data,
+    /// the same scheme as `TargetEncoder(smooth="auto")` in scikit-learn.
+    AutoTargetEncoder {
+        // global stats
+        prior: Option<T>,
+        global_target_var: Option<T>
+    },
+    /// Encodes each category with the number of rows it has, or their total weight.
+    /// Unseen categories are encoded with zero.
+    CountEncoder {
+        prior: Option<T>
+    },
 }
 
 impl<T: Data> Encoders<T> {
@@ -89,36 +368,91 @@ impl<T: Data> Encoders<T> {
         Encoders::JamesSteinEncoder { prior: None, global_target_var: None, global_target_count: None } 
     }
 
+    /// Target encoder that estimates the amount of smoothing for each category from the ratio of
+    /// the within-category target variance to the global target variance
+    pub fn new_auto_target_encoder() -> Encoders<T> {
+        Encoders::AutoTargetEncoder { prior: None, global_target_var: None }
+    }
+
+    /// Count encoder, which does not use the target
+    pub fn new_count_encoder() -> Encoders<T> {
+        Encoders::CountEncoder { prior: None }
+    }
+
+    /// Whether both are the same kind of encoder with the same parameters, regardless of their global statistics
+    pub fn same_params(&self, other: &Encoders<T>) -> bool {
+        match (self, other) {
+            (
+                Encoders::TargetEncoder { min_samples_leaf, smoothing, .. },
+                Encoders::TargetEncoder { min_samples_leaf: other_min_samples_leaf, smoothing: other_smoothing, .. },
+            ) => min_samples_leaf == other_min_samples_leaf && smoothing == other_smoothing,
+            (Encoders::JamesSteinEncoder { .. }, Encoders::JamesSteinEncoder { .. }) => true,
+            (Encoders::AutoTargetEncoder { .. }, Encoders::AutoTargetEncoder { .. }) => true,
+            (Encoders::CountEncoder { .. }, Encoders::CountEncoder { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// Prior encoding used for categories without any data. `None` until global statistics are computed.
+    pub fn prior(&self) -> Option<T> {
+        match *self {
+            Encoders::TargetEncoder { prior, .. } => prior,
+            Encoders::JamesSteinEncoder { prior, .. } => prior,
+            Encoders::AutoTargetEncoder { prior, .. } => prior,
+            Encoders::CountEncoder { prior } => prior,
+        }
+    }
+
+    /// Copy of these parameters with another `prior`, e.g. the encoding of the parent category in a hierarchy.
+    /// Other global statistics are kept.
+    pub fn with_prior(&self, prior: T) -> Encoders<T> {
+        let mut params = self.clone();
+        match &mut params {
+            Encoders::TargetEncoder { prior: p, .. } => *p = Some(prior),
+            Encoders::JamesSteinEncoder { prior: p, .. } => *p = Some(prior),
+            Encoders::AutoTargetEncoder { prior: p, .. } => *p = Some(prior),
+            Encoders::CountEncoder { prior: p } => *p = Some(prior),
+        }
+        params
+    }
+
     pub fn compute_global_stats(&mut self, target: &Array1<T>) {
+        self.set_global_stats(&target.iter().copied().collect());
+    }
+
+    /// Fill in the global statistics from already aggregated target statistics
+    pub fn set_global_stats(&mut self, target_stats: &TargetStats<T>) {
         match self {
-            Encoders::TargetEncoder { min_samples_leaf, smoothing, prior } => {
-                let a = target.mean().unwrap();
-                *prior = Some(a);
+            Encoders::TargetEncoder { prior, .. } => {
+                *prior = Some(target_stats.mean);
             },
             Encoders::JamesSteinEncoder { prior, global_target_var, global_target_count } => {
-                let tprior = target.mean().expect("Error while calculating target mean");
-                let tvar = target.var(T::one());
-                let tcount = target.len();
-
-                *prior = Some(tprior);
-                *global_target_var = Some(tvar);
-                *global_target_count = Some(tcount);
+                *prior = Some(target_stats.mean);
+                *global_target_var = Some(target_stats.var(T::one()));
+                *global_target_count = Some(target_stats.count);
+            },
+            Encoders::AutoTargetEncoder { prior, global_target_var } => {
+                *prior = Some(target_stats.mean);
+                *global_target_var = Some(target_stats.var(T::zero()));
+            },
+            Encoders::CountEncoder { prior } => {
+                *prior = Some(T::zero());
             }
         }
     }
 }
 
-fn compute_encoding<T>(encoder: &Encoders<T>, data: &Array1<OrderedFloat<T>>, target_group: &Array1<T>) -> Option<T> where T: Data {
+pub(crate) fn compute_encoding<T>(encoder: &Encoders<T>, group_stats: &TargetStats<T>, num_groups: usize) -> Option<T> where T: Data {
     match *encoder {
         Encoders::TargetEncoder {min_samples_leaf, smoothing, prior} => {
             const err_msg: &str = "Target statistics were not computed prior to encoding computation";
             let prior = prior.expect(err_msg);
-            let col_len =  T::from_usize(target_group.len())?;
+            let col_len = group_stats.weight;
 
-            if col_len == T::one() {
+            if group_stats.count == 1 {
                 Some(prior)
             } else {
-                let group_mean = target_group.mean()?;
+                let group_mean = group_stats.mean;
                 let exp_count = -(col_len - T::from_usize(min_samples_leaf)?) / T::from(smoothing)?;
                 let smoove = T::one() / (T::one() + exp_count.exp());
                 Some(prior * (T::one() - smoove) + group_mean * smoove)
@@ -126,13 +460,17 @@ fn compute_encoding<T>(encoder: &Encoders<T>, data: &Array1<OrderedFloat<T>>, ta
         },
 
         Encoders::JamesSteinEncoder { prior, global_target_var, global_target_count } => {
-            if target_group.len() == global_target_count? {
+            if group_stats.count == global_target_count? {
                 return prior;
             }
 
-            let tcat_count = T::from_usize(data.iter().unique().count())?;
-            let tlocal_target_var = target_group.var(T::one());
-            let group_mean = target_group.mean()?;    
+            let tcat_count = T::from_usize(num_groups)?;
+            let tlocal_target_var = group_stats.var(T::one());
+            if tlocal_target_var.is_nan() || global_target_var?.is_nan() {
+                // the variance is undefined for a total weight of at most one, e.g. after a time decay
+                return prior;
+            }
+            let group_mean = group_stats.mean;
 
             let mut smoothing = tlocal_target_var / (global_target_var? + tlocal_target_var) * (tcat_count - T::from_i32(3)?) / (tcat_count - T::one());
             smoothing = T::one() - smoothing;
@@ -144,197 +482,1818 @@ fn compute_encoding<T>(encoder: &Encoders<T>, data: &Array1<OrderedFloat<T>>, ta
             }
 
             Some(smoothing * group_mean + (T::one() - smoothing) * prior?)
-        }
+        },
+
+        Encoders::AutoTargetEncoder { prior, global_target_var } => {
+            let prior = prior?;
+            let global_target_var = global_target_var?;
+            let col_len = group_stats.weight;
+            let group_mean = group_stats.mean;
+            let group_var = group_stats.var(T::zero());
+
+            // shrinkage grows with the category size and shrinks with the within-category variance
+            let lambda = global_target_var * col_len / (global_target_var * col_len + group_var);
+            if lambda.is_nan() {
+                // both variances are zero, there is nothing to learn from this category
+                Some(prior)
+            } else {
+                Some(lambda * group_mean + (T::one() - lambda) * prior)
+            }
+        },
+
+        Encoders::CountEncoder { .. } => Some(group_stats.weight),
+    }
+}
+
+/// Copy of column parameters `params` with global statistics computed from `target_stats`
+pub(crate) fn column_params<D: Data>(params: &Encoders<D>, target_stats: &TargetStats<D>) -> Encoders<D> {
+    let mut params = params.clone();
+    params.set_global_stats(target_stats);
+    params
+}
+
+/// Source of each output column of an encoder of `input_columns` out of `num_input_columns`
+pub(crate) fn output_columns(input_columns: &[usize], num_input_columns: usize, remainder: Remainder) -> Vec<OutputColumn> {
+    match remainder {
+        Remainder::Passthrough => {
+            let mut output = (0..num_input_columns).map(OutputColumn::Passthrough).collect_vec();
+            for (j, &i) in input_columns.iter().enumerate() {
+                output[i] = OutputColumn::Encoded(j);
+            }
+            output
+        },
+        Remainder::Drop => (0..input_columns.len()).map(OutputColumn::Encoded).collect(),
+    }
+}
+
+/// Value of a column that is passed through by `transform`
+pub(crate) fn passthrough_value<K: Key + NumericKey, T: Data>(key: &K) -> T {
+    key.numeric_value().unwrap_or_else(|| panic!("Column with category {:?} is not numeric and can not be passed through, use `Remainder::Drop`", key))
+}
+
+fn weighted_target_stats<D: Data, T: Data>(target: &Array1<T>, weights: Option<&Array1<D>>) -> TargetStats<D> {
+    let mut target_stats = TargetStats::new();
+    for (i, t) in target.iter().enumerate() {
+        target_stats.push_weighted(D::from(*t).unwrap(), weights.map_or(D::one(), |w| w[i]));
     }
+    target_stats
 }
 
-impl<D, T> MatrixEncoder<D, T>
+impl<K, D, T> MatrixEncoder<K, D, T>
 where
+    K: Key,
     T: Data,
     D: Data,
 {
     /// Create a new `TargetEncoder` and compute target encodings for all columns.
     /// This function does not transform the original dataset. See [`transform`](TargetEncoder::transform)
-    pub fn fit(
-        data: &Array2<OrderedFloat<D>>,
+    pub fn fit<S: ndarray::Data<Elem = K>>(
+        data: &ArrayBase<S, Ix2>,
+        target: &Array1<T>,
+        encoder: &mut Encoders<D>,
+    ) -> MatrixEncoder<K, D, T> {
+        let columns = (0..data.len_of(Axis(1))).collect_vec();
+        Self::fit_weighted(data, target, &columns, None, None, encoder)
+    }
+
+    /// Same as [`fit`](MatrixEncoder::fit), but only the `columns` with these indices are encoded.
+    /// The other columns are passed through by `transform`, or dropped, see [`with_remainder`](MatrixEncoder::with_remainder).
+    pub fn fit_columns<S: ndarray::Data<Elem = K>>(
+        data: &ArrayBase<S, Ix2>,
+        target: &Array1<T>,
+        columns: &[usize],
+        encoder: &mut Encoders<D>,
+    ) -> MatrixEncoder<K, D, T> {
+        assert!(columns.iter().all(|&i| i < data.len_of(Axis(1))), "Column indices must be smaller than the number of columns");
+        assert!(columns.iter().all_unique(), "Column indices must be unique");
+        Self::fit_weighted(data, target, columns, None, None, encoder)
+    }
+
+    /// Same as [`fit`](MatrixEncoder::fit), but rows are weighted by an exponential decay on their `timestamps`,
+    /// so that recent rows dominate category statistics and the prior.
+    /// A row `half_life` older than the most recent one has half the weight.
+    pub fn fit_with_decay<S: ndarray::Data<Elem = K>>(
+        data: &ArrayBase<S, Ix2>,
+        target: &Array1<T>,
+        timestamps: &Array1<D>,
+        half_life: D,
+        encoder: &mut Encoders<D>,
+    ) -> MatrixEncoder<K, D, T> {
+        assert_eq!(timestamps.len(), target.len(), "There must be a timestamp for each target value");
+        assert!(half_life > D::zero(), "Half-life must be positive");
+        let reference_time = timestamps.fold(D::neg_infinity(), |a, &b| a.max(b));
+        let decay = TimeDecay { half_life, reference_time };
+        let weights = timestamps.mapv(|t| decay.weight(t));
+        let columns = (0..data.len_of(Axis(1))).collect_vec();
+        Self::fit_weighted(data, target, &columns, Some(&weights), Some(decay), encoder)
+    }
+
+    fn fit_weighted<S: ndarray::Data<Elem = K>>(
+        data: &ArrayBase<S, Ix2>,
         target: &Array1<T>,
+        columns: &[usize],
+        weights: Option<&Array1<D>>,
+        decay: Option<TimeDecay<D>>,
         encoder: &mut Encoders<D>,
-    ) -> MatrixEncoder<D, T> {
+    ) -> MatrixEncoder<K, D, T> {
         // TODO split into compute_global_stats(target) and compute_column_stats(data, target). The second one should be used inside ColumnEncoder
-        encoder.compute_global_stats(&target.map(|x| D::from(*x).unwrap()));
-        let mut encodings: Vec<ColumnTargetEncoder<D, T>> =
-            Vec::with_capacity(data.len_of(Axis(1)));
+        assert_eq!(target.len(), data.len_of(Axis(0)), "There must be a target value for each row");
+        let target_stats = weighted_target_stats(target, weights);
+        encoder.set_global_stats(&target_stats);
+        let mut encodings: Vec<ColumnTargetEncoder<K, D, T>> =
+            Vec::with_capacity(columns.len());
 
-        data.axis_iter(Axis(1))
-            .into_par_iter()
-            .enumerate()
-            .map(|(_, row)| {
-                // let mut owned_row = row.to_owned();
-                let mut enc = row
-                    .map(|x| OrderedFloat::<D>(**x));
-                ColumnTargetEncoder::fit(&mut enc, target, &encoder)
-            })
+        let data = data.view();
+        columns.par_iter()
+            .map(|&i| ColumnTargetEncoder::fit_view(data.column(i), target, weights, encoder))
             .collect_into_vec(&mut encodings);
 
         MatrixEncoder {
+            params: encoder.clone(),
+            target_stats,
+            decay,
+            column_names: None,
+            input_columns: columns.to_vec(),
+            num_input_columns: data.len_of(Axis(1)),
+            remainder: Remainder::Passthrough,
             encodings,
-            phantom_target: PhantomData,
+            interactions: Interactions::new(),
         }
     }
 
-    /// Performs target encoding on provided `data`
-    pub fn transform(&self, data: &mut Array2<OrderedFloat<D>>) {
-        for (i, mut row) in data.axis_iter_mut(Axis(1)).enumerate() {
-            self.encodings[i].transform_arr(&mut row);
+    /// Assemble an encoder from columns fitted elsewhere, e.g. on Arrow arrays.
+    /// Global statistics of `params` must be computed from `target_stats`.
+    #[cfg(feature = "arrow")]
+    pub(crate) fn from_columns(
+        params: Encoders<D>,
+        target_stats: TargetStats<D>,
+        encodings: Vec<ColumnTargetEncoder<K, D, T>>,
+    ) -> MatrixEncoder<K, D, T> {
+        MatrixEncoder {
+            params,
+            target_stats,
+            decay: None,
+            column_names: None,
+            input_columns: (0..encodings.len()).collect(),
+            num_input_columns: encodings.len(),
+            remainder: Remainder::Passthrough,
+            encodings,
+            interactions: Interactions::new(),
         }
     }
-}
 
-impl<D, T> ColumnTargetEncoder<D, T>
-where
-    T: Data,
-    D: Data,
-{
-    /// Create new `ColumnTargetEncoder` and compute target encodings for a single column.
-    /// This function does not transform the original dataset. See [`transform`](ColumnTargetEncoder::transform)
-    pub fn fit(
-        data: &Array1<OrderedFloat<D>>,
+    /// Fit the encoder on all rows and encode each row of `data` using only the rows strictly earlier in `order`.
+    /// Rows with the same `order` value are encoded as a block, so they never see each other's targets.
+    /// This gives leakage-free encodings for training rows that respect temporal order, while the returned
+    /// encoder uses all rows for later [`transform`](MatrixEncoder::transform) calls.
+    ///
+    /// Rows of the earliest block have no history and are encoded with `initial_prior`, or NaN if it is `None`,
+    /// so that no target leaks into them.
+    pub fn fit_transform_ordered<S: ndarray::Data<Elem = K>>(
+        data: &ArrayBase<S, Ix2>,
         target: &Array1<T>,
-        encoder: &Encoders<D>,
-    ) -> ColumnTargetEncoder<D, T> {
-        // group targets by each item in data
-        let mut data_target: Vec<_> = data.iter().zip(target).collect(); // TODO array instead of vec
-        data_target.par_sort_unstable_by_key(|x| *x.0);
+        order: &Array1<D>,
+        initial_prior: Option<D>,
+        encoder: &mut Encoders<D>,
+    ) -> (MatrixEncoder<K, D, T>, Array2<T>) {
+        let params = encoder.clone();
+        let fitted = Self::fit(data, target, encoder);
 
-        let d = data_target.iter().map(|(a, _)| *a).collect_vec();
-        let num_groups: usize = d.into_iter().map(|x| *x).dedup().count();
+        let mut rows = (0..order.len()).collect_vec();
+        rows.par_sort_by_key(|&i| OrderedFloat(order[i]));
+        let blocks = rows.iter()
+            .group_by(|&&i| OrderedFloat(order[i]))
+            .into_iter()
+            .map(|(_, block)| block.copied().collect_vec())
+            .collect_vec();
 
-        let sum = D::from(data_target.iter().map(|x| *x.1).sum::<T>()).unwrap();
-        let prior = sum / D::from(target.len()).unwrap(); // TODO this can be pushed up to MatrixEncoder
+        // global statistics of all rows before each block
+        let mut history = TargetStats::new();
+        let block_params = blocks.iter()
+            .map(|block| {
+                let block_params = if history.count == 0 {
+                    params.with_prior(initial_prior.unwrap_or_else(D::nan))
+                } else {
+                    column_params(&params, &history)
+                };
+                for &i in block {
+                    history.push(D::from(target[i]).unwrap());
+                }
+                block_params
+            })
+            .collect_vec();
 
-        let groups = data_target.into_iter().group_by(|x| *x.0);
+        let mut encoded = Array2::zeros(data.dim());
+        Zip::from(encoded.axis_iter_mut(Axis(1)))
+            .and(data.axis_iter(Axis(1)))
+            .par_for_each(|mut encoded_column, column| {
+                let mut stats: FnvHashMap<K, TargetStats<D>> = FnvHashMap::default();
+                for (block, block_params) in blocks.iter().zip(block_params.iter()) {
+                    for &i in block {
+                        let encoding = match stats.get(&column[i]) {
+                            Some(group_stats) => compute_encoding(block_params, group_stats, stats.len()),
+                            None => block_params.prior(),
+                        };
+                        encoded_column[i] = T::from(encoding.unwrap_or_else(|| panic!("Error while computing encoding for category {:?}", column[i]))).unwrap();
+                    }
+                    for &i in block {
+                        stats.entry(column[i].clone()).or_default().push(D::from(target[i]).unwrap());
+                    }
+                }
+            });
 
-        // calculate target encoding for each value in data
-        let mut encodings: FnvHashMap<OrderedFloat<D>, OrderedFloat<D>> =
-            FnvHashMap::with_capacity_and_hasher(num_groups, Default::default());
+        (fitted, encoded)
+    }
 
-        for (k, v) in &groups {
-            let target_group: Array1<D> = v.map(|x| D::from(*x.1).unwrap()).collect();
+    /// Fit the encoder on all rows and encode each row of `data` with an encoder fitted on the other folds only.
+    /// `folds` holds the fold of each row, see [`FoldSplitter`](crate::folds::FoldSplitter) for random, group-aware and
+    /// stratified assignments. Out-of-fold encodings of training rows don't leak their own targets,
+    /// while the returned encoder uses all rows for later [`transform`](MatrixEncoder::transform) calls.
+    pub fn fit_transform_oof<S: ndarray::Data<Elem = K>>(
+        data: &ArrayBase<S, Ix2>,
+        target: &Array1<T>,
+        folds: &Array1<usize>,
+        encoder: &mut Encoders<D>,
+    ) -> (MatrixEncoder<K, D, T>, Array2<T>)
+    where
+        K: DenseKey + NumericKey,
+    {
+        assert_eq!(folds.len(), data.len_of(Axis(0)), "Every row must be assigned to a fold");
+        let params = encoder.clone();
+        let fitted = Self::fit(data, target, encoder);
 
-            let encoding = compute_encoding(encoder, &data, &target_group).expect(&format!("Error while computing encoding for category {}", k));
-            encodings.insert(k, OrderedFloat(encoding));
-        }
+        let n_folds = folds.iter().max().map_or(0, |f| f + 1);
+        let mut encoded = Array2::zeros(data.dim());
+        for fold in 0..n_folds {
+            let (in_fold, out_of_fold): (Vec<usize>, Vec<usize>) = (0..folds.len()).partition(|&i| folds[i] == fold);
+            if in_fold.is_empty() || out_of_fold.is_empty() {
+                continue;
+            }
 
-        ColumnTargetEncoder {
-            encodings,
-            phantom_target: PhantomData,
+            let fold_encoder = Self::fit(
+                &data.select(Axis(0), &out_of_fold),
+                &target.select(Axis(0), &out_of_fold),
+                &mut params.clone(),
+            );
+            let fold_encoded = fold_encoder.transform_to(data.select(Axis(0), &in_fold).view());
+            for (row, &i) in fold_encoded.axis_iter(Axis(0)).zip(in_fold.iter()) {
+                encoded.row_mut(i).assign(&row);
+            }
         }
-    }
 
-    /// Encode provided `data`. If you need to transform an `ndarray` see [transform_arr](ColumnTargetEncoder::transform_arr).
-    pub fn transform(&self, data: &mut Array1<OrderedFloat<D>>) {
-        for x in data.iter_mut() {
-            *x = *self.encodings.get(x).unwrap();
-        }
+        (fitted, encoded)
     }
 
-    /// Encode provided `data`
-    pub fn transform_arr(&self, data: &mut ArrayViewMut1<OrderedFloat<D>>) {
-        data.map_mut(|x| *x = *self.encodings.get(x).unwrap());
+    /// Update the encoder with a new batch of rows without going back to the data seen before.
+    /// Category statistics and the global prior are updated incrementally, encodings are recomputed lazily on the next transform.
+    pub fn partial_fit<S: ndarray::Data<Elem = K>>(&mut self, data: &ArrayBase<S, Ix2>, target: &Array1<T>) {
+        assert!(self.decay.is_none(), "Encoder was fitted with time decay, use `partial_fit_with_decay`");
+        self.update(data, target, None);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use ndarray::Zip;
+    /// Same as [`partial_fit`](MatrixEncoder::partial_fit) for encoders created with [`fit_with_decay`](MatrixEncoder::fit_with_decay).
+    /// If the batch contains rows newer than any seen before, statistics of the previous rows are decayed accordingly.
+    /// Empty batches leave the encoder unchanged.
+    pub fn partial_fit_with_decay<S: ndarray::Data<Elem = K>>(&mut self, data: &ArrayBase<S, Ix2>, target: &Array1<T>, timestamps: &Array1<D>) {
+        let mut decay = self.decay.expect("Encoder was fitted without time decay, use `partial_fit`");
+        assert_eq!(timestamps.len(), target.len(), "There must be a timestamp for each target value");
+        if target.is_empty() {
+            return;
+        }
+        let batch_time = timestamps.fold(D::neg_infinity(), |a, &b| a.max(b));
 
-    use super::*;
+        if batch_time > decay.reference_time {
+            let previous = decay.reference_time;
+            decay.reference_time = batch_time;
+            let factor = decay.weight(previous);
 
-    #[test]
-    fn test_fit_one_column() {
-        let x = array![0., 1., 1., 0., 3., 0., 1.];
-        let mut x = x.to_ordered_float();
-        let y = array![1., 2., 2., 1., 0., 1., 2.];
+            self.target_stats.decay(factor);
+            self.encodings.par_iter_mut().for_each(|column| column.decay(factor));
+            self.decay = Some(decay);
+        }
 
-        let mut encoder = Encoders::new_target_encoder(1, 1.0);
-        encoder.compute_global_stats(&y);
+        let weights = timestamps.mapv(|t| decay.weight(t));
+        self.update(data, target, Some(&weights));
+    }
 
-        let encoder = ColumnTargetEncoder::fit(
-            &x,
-            &y,
-            &mut encoder,
-        );
-        encoder.transform(&mut x);
+    fn update<S: ndarray::Data<Elem = K>>(&mut self, data: &ArrayBase<S, Ix2>, target: &Array1<T>, weights: Option<&Array1<D>>) {
+        assert_eq!(data.len_of(Axis(1)), self.num_input_columns, "Batch must have the same number of columns as the fitted data");
+        assert_eq!(target.len(), data.len_of(Axis(0)), "There must be a target value for each row");
 
-        let expected = array![
-            1.0340579777206051,
-            1.9148550556984874,
-            1.9148550556984874,
-            1.0340579777206051,
-            1.2857142857142858,
-            1.0340579777206051,
-            1.9148550556984874,
-        ];
-        let actual = x.iter().map(|x| x.0).collect_vec();
-        expected
-            .iter()
-            .zip(actual.iter())
-            .for_each(|(expected, actual): (&f64, &f64)| {
-               assert_abs_diff_eq!(expected, actual, epsilon = 1e-4);
+        self.target_stats.merge(&weighted_target_stats(target, weights));
+        self.params.set_global_stats(&self.target_stats);
+
+        let data = data.view();
+        let target_stats = &self.target_stats;
+        self.encodings
+            .par_iter_mut()
+            .zip(self.input_columns.par_iter())
+            .for_each(|(column, &i)| {
+                let params = column_params(column.params(), target_stats);
+                column.partial_fit_view(data.column(i), target, weights, &params);
             });
+        self.interactions.partial_fit(data, target, weights, target_stats);
     }
 
-    #[test]
-    fn test_fit_one_column_james_stein() {
-        let x = array![0., 1., 1., 0., 3., 0., 1.];
-        let mut x = x.to_ordered_float();
-        let y = array![1., 2., 2., 1., 0., 1., 2.];
+    /// Recompute encodings of all columns with new encoder parameters from the stored category statistics.
+    /// This is much cheaper than [`fit`](MatrixEncoder::fit) since it only touches each category once.
+    /// Parameters set for individual columns with [`with_column_params`](MatrixEncoder::with_column_params) are replaced as well.
+    pub fn with_params(&self, encoder: &Encoders<D>) -> MatrixEncoder<K, D, T> {
+        let mut params = encoder.clone();
+        params.set_global_stats(&self.target_stats);
 
-        let mut encoder = Encoders::new_james_stein_encoder();
-        encoder.compute_global_stats(&y);
+        let encodings = self.encodings
+            .par_iter()
+            .map(|column| column.with_params(&params))
+            .collect();
+        let interactions = self.interactions.with_params(&params);
 
-        let encoder = ColumnTargetEncoder::fit(
-            &x,
-            &y,
-            &mut encoder,
-        );
-        encoder.transform(&mut x);
-        // target_encoding(&mut a, &b);
-        let expected = array![
-            1.0340579777206051,
-            1.9148550556984874,
-            1.9148550556984874,
-            1.0340579777206051,
-            1.2857142857142858,
-            1.0340579777206051,
-            1.9148550556984874,
-        ];
-        let actual: Vec<f64> = x.iter().map(|x| x.0).collect_vec();
-        expected
-            .iter()
-            .zip(actual.iter())
-            .for_each(|(expected, actual): (&f64, &f64)| {
-                println!("{} {}", expected, actual);
-                // assert_abs_diff_eq!(expected, actual, epsilon = 1e-4);
-            });
+        MatrixEncoder {
+            params,
+            target_stats: self.target_stats,
+            decay: self.decay,
+            column_names: self.column_names.clone(),
+            input_columns: self.input_columns.clone(),
+            num_input_columns: self.num_input_columns,
+            remainder: self.remainder,
+            encodings,
+            interactions,
+        }
     }
 
-    #[test]
-    fn test_fit_one_category() {
-        let mut x = Array2::<f64>::zeros((10, 7)).mapv(OrderedFloat);
-        let y = array![1., 2., 2., 1., 0., 1., 2.];
+    /// Combine two encoders fitted with the same parameters on disjoint sets of rows.
+    /// The result is the encoder that would have been fitted on all rows at once,
+    /// so shards of a dataset can be fitted independently and reduced with `merge`.
+    /// With rare grouping this only holds if both encoders folded the same categories, see [`ColumnTargetEncoder::merge`].
+    /// Interactions are matched by their columns; one chosen by [`fit_all_interactions`](MatrixEncoder::fit_all_interactions)
+    /// for only one of the encoders keeps the statistics of that encoder's rows and is appended after the others.
+    pub fn merge(self, other: MatrixEncoder<K, D, T>) -> MatrixEncoder<K, D, T> {
+        assert_eq!(self.encodings.len(), other.encodings.len(), "Merged encoders must have the same number of columns");
+        assert!(self.decay.is_none() && other.decay.is_none(), "Encoders fitted with time decay can not be merged");
+        assert!(self.params.same_params(&other.params), "Merged encoders must have the same parameters");
+        assert_eq!(self.column_names, other.column_names, "Merged encoders must have the same column names");
+        assert_eq!(self.input_columns, other.input_columns, "Merged encoders must encode the same columns");
+        assert_eq!(self.num_input_columns, other.num_input_columns, "Merged encoders must have the same number of input columns");
+        assert_eq!(self.remainder, other.remainder, "Merged encoders must have the same remainder");
 
-        let mut encoder = Encoders::new_target_encoder(1, 1.0);
-        let encoder = MatrixEncoder::fit(
-            &x,
-            &y,
-            &mut encoder,
-        );
-        encoder.transform(&mut x);
+        let mut target_stats = self.target_stats;
+        target_stats.merge(&other.target_stats);
+        let mut params = self.params;
+        params.set_global_stats(&target_stats);
 
-        for r in x.iter() {
-            assert_abs_diff_eq!(r.0,  1.2857142857142858, epsilon = 1e-8);
+        let encodings = self.encodings
+            .into_par_iter()
+            .zip(other.encodings.into_par_iter())
+            .map(|(column, other_column)| {
+                let column_params = column_params(column.params(), &target_stats);
+                column.merge(other_column, &column_params)
+            })
+            .collect();
+
+        MatrixEncoder {
+            params,
+            target_stats,
+            decay: None,
+            column_names: self.column_names,
+            input_columns: self.input_columns,
+            num_input_columns: self.num_input_columns,
+            remainder: self.remainder,
+            encodings,
+            interactions: self.interactions.merge(other.interactions, &target_stats),
+        }
+    }
+
+    /// Attach names of the encoded columns, so that data passed to `transform` can be validated with
+    /// [`check_column_names`](MatrixEncoder::check_column_names)
+    pub fn with_column_names(mut self, names: Vec<String>) -> MatrixEncoder<K, D, T> {
+        assert_eq!(names.len(), self.encodings.len(), "There must be a name for each encoded column");
+        self.column_names = Some(names);
+        self
+    }
+
+    /// Names of the encoded columns, if they were set with [`with_column_names`](MatrixEncoder::with_column_names)
+    pub fn column_names(&self) -> Option<&[String]> {
+        self.column_names.as_deref()
+    }
+
+    /// Check that `names` are the columns this encoder was fitted on, in the same order.
+    /// Encoders without column names accept any columns.
+    pub fn check_column_names(&self, names: &[String]) -> Result<(), String> {
+        match &self.column_names {
+            Some(fitted) if fitted.as_slice() != names => Err(format!(
+                "Encoder was fitted on columns {:?}, but got columns {:?}", fitted, names
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Choose when columns store encodings in a dense table indexed by integer code, see [`DenseMode`]
+    pub fn with_dense_mode(mut self, dense_mode: DenseMode) -> MatrixEncoder<K, D, T> {
+        self.encodings = self.encodings.into_iter().map(|column| column.with_dense_mode(dense_mode)).collect();
+        self
+    }
+
+    /// Use a different encoder for input column `column`, e.g. James-Stein for one column and target encoding for the others.
+    /// Encodings of the column are recomputed from its category statistics; the parameters passed to `fit` remain the default for the other columns.
+    pub fn with_column_params(mut self, column: usize, encoder: &Encoders<D>) -> MatrixEncoder<K, D, T> {
+        let j = self.input_columns.iter()
+            .position(|&i| i == column)
+            .unwrap_or_else(|| panic!("Column {} is not encoded", column));
+        self.encodings[j] = self.encodings[j].with_params(&column_params(encoder, &self.target_stats));
+        self
+    }
+
+    /// Fold infrequent categories of every column into a shared "other" category, see [`ColumnTargetEncoder::with_rare_grouping`]
+    pub fn with_rare_grouping(mut self, rare_grouping: RareGrouping) -> MatrixEncoder<K, D, T> {
+        self.encodings = self.encodings.into_par_iter().map(|column| column.with_rare_grouping(rare_grouping)).collect();
+        self
+    }
+
+    /// Choose whether [`transform_to`](MatrixEncoder::transform_to) keeps or drops the columns that are not encoded
+    pub fn with_remainder(mut self, remainder: Remainder) -> MatrixEncoder<K, D, T> {
+        self.remainder = remainder;
+        self
+    }
+
+    /// Encoders of individual columns
+    pub fn columns(&self) -> &[ColumnTargetEncoder<K, D, T>] {
+        &self.encodings
+    }
+
+    /// Indices of the input columns encoded by each of [`columns`](MatrixEncoder::columns)
+    pub fn input_columns(&self) -> &[usize] {
+        &self.input_columns
+    }
+
+    /// Number of columns of the output of [`transform_to`](MatrixEncoder::transform_to)
+    pub fn num_output_columns(&self) -> usize {
+        let num_columns = match self.remainder {
+            Remainder::Passthrough => self.num_input_columns,
+            Remainder::Drop => self.encodings.len(),
+        };
+        num_columns + self.interactions.len()
+    }
+
+    /// Also encode the combinations of categories of the input columns in each of `interactions`,
+    /// e.g. `vec![0, 2]` for the pairs of categories of columns 0 and 2. `data` and `target` must be the ones passed to `fit`.
+    /// Combinations seen in fewer than `min_support` rows are encoded with the prior.
+    /// [`transform_to`](MatrixEncoder::transform_to) appends one column per interaction to its output.
+    pub fn fit_interactions<S: ndarray::Data<Elem = K>>(
+        mut self,
+        data: &ArrayBase<S, Ix2>,
+        target: &Array1<T>,
+        interactions: &[Vec<usize>],
+        min_support: usize,
+    ) -> MatrixEncoder<K, D, T> {
+        assert_eq!(data.len_of(Axis(1)), self.num_input_columns, "Data must have the same number of columns as the fitted data");
+        assert!(self.decay.is_none(), "Interactions can not be used with time decay");
+        for columns in interactions {
+            assert!(columns.len() >= 2, "An interaction must combine at least two columns");
+            assert!(columns.iter().all(|&i| i < self.num_input_columns), "Column indices must be smaller than the number of columns");
+            assert!(columns.iter().all_unique(), "Columns of an interaction must be unique");
+        }
+        self.interactions.fit(data.view(), target, interactions, min_support, false, &self.params);
+        self
+    }
+
+    /// Same as [`fit_interactions`](MatrixEncoder::fit_interactions) for all combinations of 2 up to `max_order` encoded columns.
+    /// Interactions without any combination of categories seen in at least `min_support` rows are left out.
+    pub fn fit_all_interactions<S: ndarray::Data<Elem = K>>(
+        mut self,
+        data: &ArrayBase<S, Ix2>,
+        target: &Array1<T>,
+        max_order: usize,
+        min_support: usize,
+    ) -> MatrixEncoder<K, D, T> {
+        assert_eq!(data.len_of(Axis(1)), self.num_input_columns, "Data must have the same number of columns as the fitted data");
+        assert!(self.decay.is_none(), "Interactions can not be used with time decay");
+        let interactions = (2..=max_order)
+            .flat_map(|order| self.input_columns.iter().copied().combinations(order))
+            .collect_vec();
+        self.interactions.fit(data.view(), target, &interactions, min_support, true, &self.params);
+        self
+    }
+
+    /// Interactions fitted with [`fit_interactions`](MatrixEncoder::fit_interactions), in the order of their output columns
+    pub fn interactions(&self) -> &[Interaction<D, T>] {
+        self.interactions.interactions()
+    }
+
+    /// Drop categories of all columns and interactions with fewer than `min_count` rows, or with an encoding within `tolerance`
+    /// of the encoding of unknown categories, see [`ColumnTargetEncoder::compact`]. This shrinks fitted models of
+    /// high-cardinality columns, which can be further halved with [`with_output_type`](MatrixEncoder::with_output_type).
+    /// Statistics of the dropped categories are forgotten, so compact only once the encoder won't be updated anymore.
+    pub fn compact(&mut self, min_count: usize, tolerance: T) -> CompactionReport {
+        let categories_before = self.num_categories();
+        let bytes_before = self.memory_size();
+        self.encodings.par_iter_mut().for_each(|column| { column.compact(min_count, tolerance); });
+        self.interactions.compact(min_count, tolerance);
+        CompactionReport {
+            categories_before,
+            categories_after: self.num_categories(),
+            bytes_before,
+            bytes_after: self.memory_size(),
+        }
+    }
+
+    /// Number of categories of all columns and interactions
+    fn num_categories(&self) -> usize {
+        let columns: usize = self.encodings.iter().map(|column| column.stats().len()).sum();
+        columns + self.interactions().iter().map(|interaction| interaction.encoder().stats().len()).sum::<usize>()
+    }
+
+    /// Approximate number of bytes used by the statistics and encodings of all columns and interactions,
+    /// see [`ColumnTargetEncoder::memory_size`]
+    pub fn memory_size(&self) -> usize {
+        let columns: usize = self.encodings.iter().map(|column| column.memory_size()).sum();
+        size_of::<Self>() + columns + self.interactions.memory_size()
+    }
+
+    /// Same encoder with encodings stored as `O`, e.g. `f32`. Already computed encodings are converted and category
+    /// statistics keep their type. `T` is the type of both the targets and the encodings, so later `partial_fit` calls
+    /// take targets of type `O`.
+    pub fn with_output_type<O: Data>(self) -> MatrixEncoder<K, D, O> {
+        MatrixEncoder {
+            params: self.params,
+            target_stats: self.target_stats,
+            decay: self.decay,
+            column_names: self.column_names,
+            input_columns: self.input_columns,
+            num_input_columns: self.num_input_columns,
+            remainder: self.remainder,
+            encodings: self.encodings.into_par_iter().map(|column| column.with_output_type()).collect(),
+            interactions: self.interactions.with_output_type(),
+        }
+    }
+
+    /// Read-optimised copy of this encoder for scoring, with the encodings of each column in flat sorted arrays
+    /// instead of hash maps, see [`FrozenEncoder`]. Category statistics are left out, so it can't be updated anymore.
+    #[cfg(feature = "frozen")]
+    pub fn freeze(&self) -> FrozenEncoder<K, T>
+    where
+        K: FrozenKey,
+        T: Pod,
+    {
+        assert!(self.interactions().is_empty(), "Encoders with interactions can not be frozen");
+        FrozenEncoder::new(
+            params_f64(&self.params),
+            self.column_names.clone(),
+            self.input_columns.clone(),
+            self.num_input_columns,
+            self.remainder,
+            self.encodings.par_iter().map(|column| column.freeze()).collect(),
+        )
+    }
+
+    /// Source of each output column
+    fn output_columns(&self) -> Vec<OutputColumn> {
+        output_columns(&self.input_columns, self.num_input_columns, self.remainder)
+    }
+
+    /// Value of an output column for an input `row`
+    fn output_value(&self, column: OutputColumn, row: ArrayView1<K>) -> T
+    where
+        K: DenseKey + NumericKey,
+    {
+        match column {
+            OutputColumn::Encoded(j) => self.encodings[j].encode(&row[self.input_columns[j]]),
+            OutputColumn::Passthrough(i) => passthrough_value(&row[i]),
+        }
+    }
+
+    /// Default parameters and global target statistics this encoder was fitted with.
+    /// Parameters of each column are available from its encoder in [`columns`](MatrixEncoder::columns).
+    pub fn params(&self) -> &Encoders<D> {
+        &self.params
+    }
+
+    /// Compute encodings that are out of date after partial_fit, merge or with_params in parallel
+    fn refresh_encodings(&self)
+    where
+        K: DenseKey,
+    {
+        self.encodings.par_iter().for_each(|column| { column.dense(); });
+    }
+
+    /// Encode provided `data` into a new array with the same memory layout. Categories not seen during fit are encoded with the prior.
+    /// Columns that are not encoded are passed through or dropped, see [`with_remainder`](MatrixEncoder::with_remainder).
+    /// Encodings of interactions are appended after the other columns.
+    pub fn transform_to(&self, data: ArrayView2<K>) -> Array2<T>
+    where
+        K: DenseKey + NumericKey,
+    {
+        let dim = (data.len_of(Axis(0)), self.num_output_columns());
+        let mut encoded = Array2::zeros(dim.set_f(!data.is_standard_layout()));
+        self.transform_into(data, encoded.view_mut());
+        encoded
+    }
+
+    /// Encode provided `data` into the preallocated array `encoded` with [`num_output_columns`](MatrixEncoder::num_output_columns).
+    /// Row-major data is processed in parallel chunks of rows, other layouts in parallel over columns and chunks of rows within each column.
+    pub fn transform_into(&self, data: ArrayView2<K>, encoded: ArrayViewMut2<T>)
+    where
+        K: DenseKey + NumericKey,
+    {
+        assert_eq!(data.len_of(Axis(1)), self.num_input_columns, "Data must have the same number of columns as the fitted data");
+        assert_eq!(encoded.dim(), (data.len_of(Axis(0)), self.num_output_columns()), "Output must have a row for each row of the data and a column for each output column");
+        self.refresh_encodings();
+        let output_columns = self.output_columns();
+        let (mut encoded, interactions_encoded) = encoded.split_at(Axis(1), output_columns.len());
+        self.interactions.transform_into(data, interactions_encoded);
+
+        if data.is_standard_layout() {
+            encoded.axis_chunks_iter_mut(Axis(0), TRANSFORM_ROW_CHUNK)
+                .into_par_iter()
+                .zip(data.axis_chunks_iter(Axis(0), TRANSFORM_ROW_CHUNK).into_par_iter())
+                .for_each(|(mut encoded_chunk, chunk)| {
+                    for (mut encoded_row, row) in encoded_chunk.rows_mut().into_iter().zip(chunk.rows()) {
+                        for (e, &column) in encoded_row.iter_mut().zip(output_columns.iter()) {
+                            *e = self.output_value(column, row);
+                        }
+                    }
+                });
+        } else {
+            encoded.axis_iter_mut(Axis(1))
+                .into_par_iter()
+                .zip(output_columns.par_iter())
+                .for_each(|(mut encoded_column, &output_column)| {
+                    let (input_column, encoder) = match output_column {
+                        OutputColumn::Encoded(j) => (self.input_columns[j], Some(&self.encodings[j])),
+                        OutputColumn::Passthrough(i) => (i, None),
+                    };
+                    encoded_column.axis_chunks_iter_mut(Axis(0), TRANSFORM_ROW_CHUNK)
+                        .into_par_iter()
+                        .zip(data.column(input_column).axis_chunks_iter(Axis(0), TRANSFORM_ROW_CHUNK).into_par_iter())
+                        .for_each(|(mut encoded_chunk, chunk)| match encoder {
+                            Some(encoder) => Zip::from(&mut encoded_chunk).and(&chunk).for_each(|e, k| *e = encoder.encode(k)),
+                            None => Zip::from(&mut encoded_chunk).and(&chunk).for_each(|e, k| *e = passthrough_value(k)),
+                        });
+                });
+        }
+    }
+}
+
+impl<D, T> MatrixEncoder<OrderedFloat<D>, D, T>
+where
+    T: Data,
+    D: Data,
+{
+    /// Same as [`fit`](MatrixEncoder::fit) for plain floats, which are used as keys without copying
+    pub fn fit_floats(data: ArrayView2<D>, target: &Array1<T>, encoder: &mut Encoders<D>) -> MatrixEncoder<OrderedFloat<D>, D, T> {
+        Self::fit(&as_ordered_float(data), target, encoder)
+    }
+
+    /// Same as [`transform_into`](MatrixEncoder::transform_into) for plain floats, which are used as keys without copying
+    pub fn transform_floats_into(&self, data: ArrayView2<D>, encoded: ArrayViewMut2<T>) {
+        self.transform_into(as_ordered_float(data), encoded);
+    }
+
+    /// Performs target encoding on provided `data` in place, in parallel as in [`transform_to`](MatrixEncoder::transform_to).
+    /// Columns that are not encoded are left untouched. Categories not seen during fit are encoded with the prior,
+    /// where earlier versions panicked.
+    pub fn transform(&self, data: &mut Array2<OrderedFloat<D>>) {
+        assert_eq!(data.len_of(Axis(1)), self.num_input_columns, "Data must have the same number of columns as the fitted data");
+        assert_eq!(self.remainder, Remainder::Passthrough, "Columns can not be dropped in place, use `transform_to`");
+        assert!(self.interactions().is_empty(), "Interactions can not be appended in place, use `transform_to`");
+        self.refresh_encodings();
+
+        if data.is_standard_layout() {
+            data.axis_chunks_iter_mut(Axis(0), TRANSFORM_ROW_CHUNK)
+                .into_par_iter()
+                .for_each(|mut chunk| {
+                    for mut row in chunk.rows_mut() {
+                        for (&i, encoder) in self.input_columns.iter().zip(self.encodings.iter()) {
+                            row[i] = OrderedFloat(D::from(encoder.encode(&row[i])).unwrap());
+                        }
+                    }
+                });
+        } else {
+            let mut columns = data.axis_iter_mut(Axis(1)).map(Some).collect_vec();
+            let selected = self.input_columns.iter().map(|&i| columns[i].take().unwrap()).collect_vec();
+            selected.into_par_iter()
+                .zip(self.encodings.par_iter())
+                .for_each(|(mut column, encoder)| {
+                    column.axis_chunks_iter_mut(Axis(0), TRANSFORM_ROW_CHUNK)
+                        .into_par_iter()
+                        .for_each(|mut chunk| encoder.transform_arr(&mut chunk));
+                });
+        }
+    }
+}
+
+impl<K, D, T> ColumnTargetEncoder<K, D, T>
+where
+    K: Key,
+    T: Data,
+    D: Data,
+{
+    /// Create new `ColumnTargetEncoder` and compute target encodings for a single column.
+    /// This function does not transform the original dataset. See [`transform`](ColumnTargetEncoder::transform)
+    pub fn fit(
+        data: &Array1<K>,
+        target: &Array1<T>,
+        encoder: &Encoders<D>,
+    ) -> ColumnTargetEncoder<K, D, T> {
+        Self::fit_weighted(data, target, None, encoder)
+    }
+
+    /// Same as [`fit`](ColumnTargetEncoder::fit), but each row contributes to category statistics with its weight
+    pub fn fit_weighted(
+        data: &Array1<K>,
+        target: &Array1<T>,
+        weights: Option<&Array1<D>>,
+        encoder: &Encoders<D>,
+    ) -> ColumnTargetEncoder<K, D, T> {
+        Self::fit_view(data.view(), target, weights, encoder)
+    }
+
+    fn fit_view(
+        data: ArrayView1<K>,
+        target: &Array1<T>,
+        weights: Option<&Array1<D>>,
+        encoder: &Encoders<D>,
+    ) -> ColumnTargetEncoder<K, D, T> {
+        assert_eq!(target.len(), data.len(), "There must be a target value for each row");
+        let column_encoder = ColumnTargetEncoder {
+            params: encoder.clone(),
+            stats: Self::collect_stats(data, target, weights),
+            dense_mode: DenseMode::Auto,
+            min_count: 1,
+            rare_grouping: None,
+            rare_stats: TargetStats::new(),
+            encodings: OnceLock::new(),
+        };
+        column_encoder.encodings();
+        column_encoder
+    }
+
+    /// Create an encoder from already collected category statistics. Global statistics of `encoder` must be computed.
+    #[cfg(feature = "arrow")]
+    pub(crate) fn from_stats(stats: FnvHashMap<K, TargetStats<D>>, encoder: &Encoders<D>) -> ColumnTargetEncoder<K, D, T> {
+        ColumnTargetEncoder {
+            params: encoder.clone(),
+            stats,
+            dense_mode: DenseMode::Auto,
+            min_count: 1,
+            rare_grouping: None,
+            rare_stats: TargetStats::new(),
+            encodings: OnceLock::new(),
+        }
+    }
+
+    /// Same encoder with each category `k` replaced by `f(k)`, which must map different categories to different ones.
+    /// Encodings are recomputed lazily on the next transform.
+    pub(crate) fn map_keys<L: Key>(self, f: impl Fn(K) -> L) -> ColumnTargetEncoder<L, D, T> {
+        ColumnTargetEncoder {
+            params: self.params,
+            stats: self.stats.into_iter().map(|(k, group_stats)| (f(k), group_stats)).collect(),
+            dense_mode: self.dense_mode,
+            min_count: self.min_count,
+            rare_grouping: self.rare_grouping,
+            rare_stats: self.rare_stats,
+            encodings: OnceLock::new(),
+        }
+    }
+
+    /// Update category statistics with a new batch of rows. Encodings are recomputed lazily on the next transform.
+    /// Global statistics of `encoder` must be computed on all rows seen so far, including this batch.
+    pub fn partial_fit(&mut self, data: &Array1<K>, target: &Array1<T>, encoder: &Encoders<D>) {
+        self.partial_fit_weighted(data, target, None, encoder);
+    }
+
+    /// Same as [`partial_fit`](ColumnTargetEncoder::partial_fit) with weighted rows
+    pub fn partial_fit_weighted(&mut self, data: &Array1<K>, target: &Array1<T>, weights: Option<&Array1<D>>, encoder: &Encoders<D>) {
+        self.partial_fit_view(data.view(), target, weights, encoder);
+    }
+
+    fn partial_fit_view(&mut self, data: ArrayView1<K>, target: &Array1<T>, weights: Option<&Array1<D>>, encoder: &Encoders<D>) {
+        assert_eq!(target.len(), data.len(), "There must be a target value for each row");
+        for (k, batch_stats) in Self::collect_stats(data, target, weights).into_iter() {
+            self.stats.entry(k).or_default().merge(&batch_stats);
+        }
+        self.fold_rare_categories();
+        self.params = encoder.clone();
+        self.encodings = OnceLock::new();
+    }
+
+    /// Scale weights of all rows seen so far by `factor`. Encodings are recomputed lazily on the next transform.
+    pub fn decay(&mut self, factor: D) {
+        for group_stats in self.stats.values_mut() {
+            group_stats.decay(factor);
+        }
+        self.rare_stats.decay(factor);
+        self.encodings = OnceLock::new();
+    }
+
+    /// Create a new `ColumnTargetEncoder` from the category statistics of this one, recomputing the encodings with `encoder`.
+    /// Global statistics of `encoder` must be already computed.
+    pub fn with_params(&self, encoder: &Encoders<D>) -> ColumnTargetEncoder<K, D, T> {
+        ColumnTargetEncoder {
+            params: encoder.clone(),
+            stats: self.stats.clone(),
+            dense_mode: self.dense_mode,
+            min_count: self.min_count,
+            rare_grouping: self.rare_grouping,
+            rare_stats: self.rare_stats,
+            encodings: OnceLock::new(),
+        }
+    }
+
+    /// Combine category statistics of two encoders fitted on disjoint sets of rows and recompute the encodings with `encoder`.
+    /// Global statistics of `encoder` must be computed on the union of both sets. Both encoders must have the same
+    /// parameters, `min_count` and [`rare_grouping`](ColumnTargetEncoder::with_rare_grouping).
+    ///
+    /// Categories are folded again after merging, but the statistics of categories that were already folded are only kept
+    /// in the "other" category. A category folded in one encoder but not in the other keeps the rows of the other encoder
+    /// only, so the result differs from a fit on all rows. Merge before setting rare grouping to get the same result.
+    pub fn merge(mut self, other: ColumnTargetEncoder<K, D, T>, encoder: &Encoders<D>) -> ColumnTargetEncoder<K, D, T> {
+        assert!(self.params.same_params(&other.params), "Merged encoders must have the same parameters");
+        assert_eq!(self.min_count, other.min_count, "Merged encoders must have the same minimum count");
+        assert_eq!(self.rare_grouping, other.rare_grouping, "Merged encoders must have the same rare grouping");
+        for (k, other_stats) in other.stats.into_iter() {
+            self.stats.entry(k).or_default().merge(&other_stats);
+        }
+        self.rare_stats.merge(&other.rare_stats);
+        self.fold_rare_categories();
+        self.params = encoder.clone();
+        self.encodings = OnceLock::new();
+        self
+    }
+
+    /// Collect target statistics of each category, with hash aggregation for columns that look low-cardinality
+    /// on a sample of rows and by sorting otherwise
+    fn collect_stats(data: ArrayView1<K>, target: &Array1<T>, weights: Option<&Array1<D>>) -> FnvHashMap<K, TargetStats<D>> {
+        let step = (data.len() / AGGREGATION_SAMPLE_SIZE).max(1);
+        let sample = data.slice(s![..;step]);
+        let distinct = sample.iter().collect::<FnvHashSet<&K>>().len();
+
+        if distinct * HASH_AGGREGATION_MAX_RATIO <= sample.len() {
+            Self::collect_stats_hashed(data, target, weights)
+        } else {
+            Self::collect_stats_sorted(data, target, weights)
+        }
+    }
+
+    /// Single pass aggregation into partial hash maps for chunks of rows, merged at the end
+    fn collect_stats_hashed(data: ArrayView1<K>, target: &Array1<T>, weights: Option<&Array1<D>>) -> FnvHashMap<K, TargetStats<D>> {
+        (0..data.len())
+            .into_par_iter()
+            .with_min_len(HASH_AGGREGATION_CHUNK)
+            .fold(FnvHashMap::default, |mut stats: FnvHashMap<K, TargetStats<D>>, i| {
+                let (t, w) = (D::from(target[i]).unwrap(), weights.map_or(D::one(), |w| w[i]));
+                match stats.get_mut(&data[i]) {
+                    Some(group_stats) => group_stats.push_weighted(t, w),
+                    None => {
+                        let mut group_stats = TargetStats::new();
+                        group_stats.push_weighted(t, w);
+                        stats.insert(data[i].clone(), group_stats);
+                    }
+                }
+                stats
+            })
+            .reduce(FnvHashMap::default, |a, b| {
+                let (mut larger, smaller) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+                for (k, group_stats) in smaller.into_iter() {
+                    larger.entry(k).or_default().merge(&group_stats);
+                }
+                larger
+            })
+    }
+
+    fn collect_stats_sorted(data: ArrayView1<K>, target: &Array1<T>, weights: Option<&Array1<D>>) -> FnvHashMap<K, TargetStats<D>> {
+        // group targets by each item in data
+        let mut data_target: Vec<_> = data.iter()
+            .zip(target)
+            .enumerate()
+            .map(|(i, (k, t))| (k, D::from(*t).unwrap(), weights.map_or(D::one(), |w| w[i])))
+            .collect(); // TODO array instead of vec
+        data_target.par_sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+        let num_groups: usize = data_target.iter().map(|(a, _, _)| *a).dedup().count();
+
+        let groups = data_target.into_iter().group_by(|x| x.0);
+
+        // collect sufficient statistics of the target for each value in data
+        let mut stats: FnvHashMap<K, TargetStats<D>> =
+            FnvHashMap::with_capacity_and_hasher(num_groups, Default::default());
+
+        for (k, v) in &groups {
+            let group_stats = stats.entry(k.clone()).or_default();
+            for (_, t, w) in v {
+                group_stats.push_weighted(t, w);
+            }
+        }
+        stats
+    }
+
+    /// Choose when to store encodings in a dense table indexed by integer code. The default is [`DenseMode::Auto`].
+    pub fn with_dense_mode(mut self, dense_mode: DenseMode) -> ColumnTargetEncoder<K, D, T> {
+        self.dense_mode = dense_mode;
+        self.encodings = OnceLock::new();
+        self
+    }
+
+    /// Encode categories with fewer than `min_count` rows with the prior, as if they were not seen during fit.
+    /// Their statistics are kept, so they get an encoding of their own once they reach `min_count` rows in [`partial_fit`](ColumnTargetEncoder::partial_fit).
+    pub fn with_min_count(mut self, min_count: usize) -> ColumnTargetEncoder<K, D, T> {
+        self.min_count = min_count;
+        self.encodings = OnceLock::new();
+        self
+    }
+
+    /// Fold infrequent categories into a shared "other" category, which is encoded from the statistics of all of them.
+    /// Folded categories are removed from the encodings, and categories that are not in the encodings, including
+    /// categories not seen during fit, are encoded as the "other" category. Folding is repeated after each `partial_fit`.
+    pub fn with_rare_grouping(mut self, rare_grouping: RareGrouping) -> ColumnTargetEncoder<K, D, T> {
+        self.rare_grouping = Some(rare_grouping);
+        self.fold_rare_categories();
+        self.encodings = OnceLock::new();
+        self
+    }
+
+    fn fold_rare_categories(&mut self) {
+        let rare_grouping = match self.rare_grouping {
+            Some(rare_grouping) => rare_grouping,
+            None => return,
+        };
+
+        let mut by_frequency = self.stats.iter().map(|(k, group_stats)| (group_stats.count, k)).collect_vec();
+        by_frequency.sort_unstable_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        let kept = by_frequency.iter()
+            .take_while(|(count, _)| *count >= rare_grouping.min_frequency)
+            .count()
+            .min(rare_grouping.max_categories.unwrap_or(usize::MAX));
+        let rare = by_frequency[kept..].iter().map(|(_, k)| (*k).clone()).collect_vec();
+
+        for k in rare {
+            let group_stats = self.stats.remove(&k).unwrap();
+            self.rare_stats.merge(&group_stats);
+        }
+    }
+
+    /// Statistics of the categories folded into the "other" category by [`with_rare_grouping`](ColumnTargetEncoder::with_rare_grouping)
+    pub fn rare_stats(&self) -> &TargetStats<D> {
+        &self.rare_stats
+    }
+
+    /// Drop categories with fewer than `min_count` rows, or with an encoding within `tolerance` of the encoding of unknown
+    /// categories, which they are encoded with from then on. Encodings of the remaining categories are kept as they are.
+    /// Statistics of the dropped categories are forgotten, so they start over in later `partial_fit` calls.
+    /// Encodings recomputed by later [`with_params`](ColumnTargetEncoder::with_params), `partial_fit` or
+    /// [`merge`](ColumnTargetEncoder::merge) calls don't count the dropped categories either, e.g. in the number of
+    /// categories of James-Stein encoding, so they differ from the encodings of an encoder that was not compacted.
+    /// Returns the number of dropped categories.
+    pub fn compact(&mut self, min_count: usize, tolerance: T) -> usize {
+        self.lookup();
+        let mut encodings = self.encodings.take().unwrap();
+        let stats = &self.stats;
+        let unknown = encodings.unknown;
+        encodings.map.retain(|k, encoding| stats[k].count >= min_count && (*encoding - unknown).abs() > tolerance);
+        encodings.map.shrink_to_fit();
+
+        let num_categories = self.stats.len();
+        self.stats.retain(|k, _| encodings.map.contains_key(k));
+        self.stats.shrink_to_fit();
+        encodings.dense = OnceLock::new();
+        self.encodings = OnceLock::from(encodings);
+        num_categories - self.stats.len()
+    }
+
+    /// Same encoder with encodings stored as `O`, e.g. `f32` to halve the memory of the encodings of an `f64` encoder.
+    /// Already computed encodings are converted, category statistics keep their type. Later `partial_fit` calls
+    /// take targets of type `O`, which is the type of both the targets and the encodings.
+    pub fn with_output_type<O: Data>(self) -> ColumnTargetEncoder<K, D, O> {
+        let to_output = |encoding: T| O::from(encoding).unwrap();
+        let encodings = match self.encodings.into_inner() {
+            Some(encodings) => OnceLock::from(Encodings {
+                map: encodings.map.into_iter().map(|(k, encoding)| (k, to_output(encoding))).collect(),
+                dense: match encodings.dense.into_inner() {
+                    Some(dense) => OnceLock::from(dense.map(|dense| dense.into_iter().map(to_output).collect())),
+                    None => OnceLock::new(),
+                },
+                unknown: to_output(encodings.unknown),
+            }),
+            None => OnceLock::new(),
+        };
+        ColumnTargetEncoder {
+            params: self.params,
+            stats: self.stats,
+            dense_mode: self.dense_mode,
+            min_count: self.min_count,
+            rare_grouping: self.rare_grouping,
+            rare_stats: self.rare_stats,
+            encodings,
+        }
+    }
+
+    /// Approximate number of bytes used by the statistics and encodings, not counting memory owned by the categories
+    /// themselves such as the contents of `String` keys
+    pub fn memory_size(&self) -> usize {
+        // hash maps store a control byte next to each entry
+        let stats = self.stats.capacity() * (size_of::<K>() + size_of::<TargetStats<D>>() + 1);
+        let encodings = self.encodings.get().map_or(0, |encodings| {
+            encodings.map.capacity() * (size_of::<K>() + size_of::<T>() + 1)
+                + encodings.dense.get().and_then(Option::as_ref).map_or(0, |dense| dense.capacity() * size_of::<T>())
+        });
+        size_of::<Self>() + stats + encodings
+    }
+
+    /// Read-optimised copy of the encodings of this column, see [`FrozenColumn`]
+    #[cfg(feature = "frozen")]
+    pub fn freeze(&self) -> FrozenColumn<K, T>
+    where
+        K: FrozenKey,
+        T: Pod,
+    {
+        let lookup = self.lookup();
+        FrozenColumn::new(&lookup.map, lookup.unknown, params_f64(&self.params))
+    }
+
+    /// Whether encodings are looked up in a dense table indexed by integer code
+    pub fn is_dense(&self) -> bool
+    where
+        K: DenseKey,
+    {
+        self.dense().is_some()
+    }
+
+    /// Dense table of the encodings, built on first use if the categories are integer codes, see [`DenseMode`]
+    fn dense(&self) -> Option<&[T]>
+    where
+        K: DenseKey,
+    {
+        let lookup = self.lookup();
+        lookup.dense.get_or_init(|| self.dense_table(&lookup.map, lookup.unknown)).as_deref()
+    }
+
+    /// Encodings computed from the category statistics on first use
+    fn lookup(&self) -> &Encodings<K, T> {
+        self.encodings.get_or_init(|| {
+            let supported = self.stats.iter().filter(|(_, group_stats)| group_stats.count >= self.min_count).collect_vec();
+            let num_groups = supported.len();
+            let mut encodings = FnvHashMap::with_capacity_and_hasher(num_groups, Default::default());
+
+            for (k, group_stats) in supported {
+                let encoding = compute_encoding(&self.params, group_stats, num_groups)
+                    .unwrap_or_else(|| panic!("Error while computing encoding for category {:?}", k));
+                encodings.insert(k.clone(), T::from(encoding).unwrap());
+            }
+            let unknown = if self.rare_stats.count > 0 {
+                compute_encoding(&self.params, &self.rare_stats, num_groups + 1).expect("Error while computing encoding for rare categories")
+            } else {
+                self.params.prior().expect("Target statistics were not computed prior to encoding computation")
+            };
+            let unknown = T::from(unknown).unwrap();
+            Encodings { map: encodings, dense: OnceLock::new(), unknown }
+        })
+    }
+
+    fn dense_table(&self, encodings: &FnvHashMap<K, T>, unknown: T) -> Option<Vec<T>>
+    where
+        K: DenseKey,
+    {
+        if self.dense_mode == DenseMode::Never {
+            return None;
+        }
+        let codes = encodings.keys().map(|k| k.dense_code()).collect::<Option<Vec<usize>>>()?;
+        let len = codes.iter().max().map_or(0, |&c| c.saturating_add(1));
+        if len > DENSE_MAX_LEN {
+            return None;
+        }
+        if self.dense_mode == DenseMode::Auto && len > (DENSE_MAX_RATIO * encodings.len()).max(DENSE_MIN_LEN) {
+            return None;
+        }
+
+        let mut dense = vec![unknown; len];
+        for (code, encoding) in codes.into_iter().zip(encodings.values()) {
+            dense[code] = *encoding;
+        }
+        Some(dense)
+    }
+
+    /// Encodings for each category seen during fit, computed from the category statistics on first use
+    pub fn encodings(&self) -> &FnvHashMap<K, T> {
+        &self.lookup().map
+    }
+
+    /// Parameters and global target statistics the encodings are computed with
+    pub fn params(&self) -> &Encoders<D> {
+        &self.params
+    }
+
+    /// Target statistics collected for each category during fit
+    pub fn stats(&self) -> &FnvHashMap<K, TargetStats<D>> {
+        &self.stats
+    }
+
+    /// Encoding for categories that were not seen during fit: the prior, or the "other" category with rare grouping
+    pub(crate) fn unknown_encoding(&self) -> T {
+        self.lookup().unknown
+    }
+
+    /// Encoding of a single category. Categories not seen during fit are encoded with the prior,
+    /// or as the "other" category with [`rare_grouping`](ColumnTargetEncoder::with_rare_grouping).
+    pub fn encode(&self, key: &K) -> T
+    where
+        K: DenseKey,
+    {
+        let lookup = self.lookup();
+        if let (Some(dense), Some(code)) = (self.dense(), key.dense_code()) {
+            return dense.get(code).copied().unwrap_or(lookup.unknown);
+        }
+        lookup.map.get(key).copied().unwrap_or(lookup.unknown)
+    }
+
+    /// Encode provided `data` into a new array. Categories not seen during fit are encoded with the prior.
+    pub fn transform_to(&self, data: ArrayView1<K>) -> Array1<T>
+    where
+        K: DenseKey,
+    {
+        data.map(|k| self.encode(k))
+    }
+}
+
+impl<D, T> ColumnTargetEncoder<OrderedFloat<D>, D, T>
+where
+    T: Data,
+    D: Data,
+{
+    /// Encode provided `data`. Categories not seen during fit are encoded with the prior, where earlier versions panicked.
+    /// If you need to transform an `ndarray` see [transform_arr](ColumnTargetEncoder::transform_arr).
+    pub fn transform(&self, data: &mut Array1<OrderedFloat<D>>) {
+        for x in data.iter_mut() {
+            *x = OrderedFloat(D::from(self.encode(x)).unwrap());
+        }
+    }
+
+    /// Encode provided `data`. Categories not seen during fit are encoded with the prior.
+    pub fn transform_arr(&self, data: &mut ArrayViewMut1<OrderedFloat<D>>) {
+        data.map_mut(|x| *x = OrderedFloat(D::from(self.encode(x)).unwrap()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Zip;
+
+    use super::*;
+    use crate::folds::FoldSplitter;
+
+    #[test]
+    fn test_fit_one_column() {
+        let x = array![0., 1., 1., 0., 3., 0., 1.];
+        let mut x = x.to_ordered_float();
+        let y = array![1., 2., 2., 1., 0., 1., 2.];
+
+        let mut encoder = Encoders::new_target_encoder(1, 1.0);
+        encoder.compute_global_stats(&y);
+
+        let encoder = ColumnTargetEncoder::fit(
+            &x,
+            &y,
+            &mut encoder,
+        );
+        encoder.transform(&mut x);
+
+        let expected = array![
+            1.0340579777206051,
+            1.9148550556984874,
+            1.9148550556984874,
+            1.0340579777206051,
+            1.2857142857142858,
+            1.0340579777206051,
+            1.9148550556984874,
+        ];
+        let actual = x.iter().map(|x| x.0).collect_vec();
+        expected
+            .iter()
+            .zip(actual.iter())
+            .for_each(|(expected, actual): (&f64, &f64)| {
+               assert_abs_diff_eq!(expected, actual, epsilon = 1e-4);
+            });
+    }
+
+    #[test]
+    fn test_fit_one_column_james_stein() {
+        let x = array![0., 1., 1., 0., 3., 0., 1.];
+        let mut x = x.to_ordered_float();
+        let y = array![1., 2., 2., 1., 0., 1., 2.];
+
+        let mut encoder = Encoders::new_james_stein_encoder();
+        encoder.compute_global_stats(&y);
+
+        let encoder = ColumnTargetEncoder::fit(
+            &x,
+            &y,
+            &mut encoder,
+        );
+        encoder.transform(&mut x);
+        // target_encoding(&mut a, &b);
+        let expected = array![
+            1.0340579777206051,
+            1.9148550556984874,
+            1.9148550556984874,
+            1.0340579777206051,
+            1.2857142857142858,
+            1.0340579777206051,
+            1.9148550556984874,
+        ];
+        let actual: Vec<f64> = x.iter().map(|x| x.0).collect_vec();
+        expected
+            .iter()
+            .zip(actual.iter())
+            .for_each(|(expected, actual): (&f64, &f64)| {
+                println!("{} {}", expected, actual);
+                // assert_abs_diff_eq!(expected, actual, epsilon = 1e-4);
+            });
+    }
+
+    #[test]
+    fn test_fit_one_column_auto_smoothing() {
+        let x = array![0., 1., 1., 0., 3., 0., 1.];
+        let mut x = x.to_ordered_float();
+        let y = array![1., 2., 3., 1., 0., 2., 2.];
+
+        let mut encoder = Encoders::new_auto_target_encoder();
+        encoder.compute_global_stats(&y);
+
+        let encoder = ColumnTargetEncoder::fit(
+            &x,
+            &y,
+            &mut encoder,
+        );
+        encoder.transform(&mut x);
+
+        // a singleton category has zero variance, so it keeps its own mean
+        let expected = array![
+            1.3531409168081494,
+            2.269949066213922,
+            2.269949066213922,
+            1.3531409168081494,
+            0.0,
+            1.3531409168081494,
+            2.269949066213922,
+        ];
+        let actual = x.iter().map(|x| x.0).collect_vec();
+        expected
+            .iter()
+            .zip(actual.iter())
+            .for_each(|(expected, actual): (&f64, &f64)| {
+               assert_abs_diff_eq!(expected, actual, epsilon = 1e-4);
+            });
+    }
+
+    #[test]
+    fn test_fit_one_category() {
+        let mut x = Array2::<f64>::zeros((7, 10)).mapv(OrderedFloat);
+        let y = array![1., 2., 2., 1., 0., 1., 2.];
+
+        let mut encoder = Encoders::new_target_encoder(1, 1.0);
+        let encoder = MatrixEncoder::fit(
+            &x,
+            &y,
+            &mut encoder,
+        );
+        encoder.transform(&mut x);
+
+        for r in x.iter() {
+            assert_abs_diff_eq!(r.0,  1.2857142857142858, epsilon = 1e-8);
+        }
+    }
+
+    #[test]
+    fn test_with_params_matches_refit() {
+        let data = array![
+            [2., 6., 3., 5., 4.],
+            [3., 2., 2., 5., 3.],
+            [8., 4., 5., 3., 1.],
+            [5., 0., 2., 4., 9.],
+            [9., 5., 2., 0., 7.]
+        ].mapv(OrderedFloat);
+        let target = array![0.48263811, 0.16705367, 0.32397016, 0.10172379, 0.54362169];
+
+        let encoder = MatrixEncoder::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0));
+        let refitted = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(2, 5.0));
+        let updated = encoder.with_params(&Encoders::new_target_encoder(2, 5.0));
+
+        let mut expected = data.clone();
+        refitted.transform(&mut expected);
+        let mut actual = data.clone();
+        updated.transform(&mut actual);
+
+        Zip::from(&actual).and(&expected).for_each(|&a, &e| {
+            assert_abs_diff_eq!(a.0, e.0, epsilon = 1e-8);
+        });
+    }
+
+    #[test]
+    fn test_merge_matches_fit_on_union() {
+        let data = array![
+            [2., 6., 3., 5., 4.],
+            [3., 2., 2., 5., 3.],
+            [8., 4., 5., 3., 1.],
+            [5., 0., 2., 4., 9.],
+            [9., 5., 2., 0., 7.],
+            [2., 4., 3., 5., 1.]
+        ].mapv(OrderedFloat);
+        let target = array![0.48263811, 0.16705367, 0.32397016, 0.10172379, 0.54362169, 0.2];
+
+        let full = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_auto_target_encoder());
+        let left = MatrixEncoder::<_, f64, f64>::fit(
+            &data.slice(s![..3, ..]).to_owned(),
+            &target.slice(s![..3]).to_owned(),
+            &mut Encoders::new_auto_target_encoder(),
+        );
+        let right = MatrixEncoder::<_, f64, f64>::fit(
+            &data.slice(s![3.., ..]).to_owned(),
+            &target.slice(s![3..]).to_owned(),
+            &mut Encoders::new_auto_target_encoder(),
+        );
+        let merged = left.merge(right);
+
+        let mut expected = data.clone();
+        full.transform(&mut expected);
+        let mut actual = data.clone();
+        merged.transform(&mut actual);
+
+        Zip::from(&actual).and(&expected).for_each(|&a, &e| {
+            assert_abs_diff_eq!(a.0, e.0, epsilon = 1e-8);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Merged encoders must have the same parameters")]
+    fn test_merge_rejects_different_params() {
+        let data = array![[1_i64], [2], [1]];
+        let target = array![0.5, 0.2, 0.1];
+        let left = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0));
+        let right = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 5.0));
+        left.merge(right);
+    }
+
+    #[test]
+    fn test_partial_fit_matches_fit() {
+        let data = array![
+            [2., 6., 3., 5., 4.],
+            [3., 2., 2., 5., 3.],
+            [8., 4., 5., 3., 1.],
+            [5., 0., 2., 4., 9.],
+            [9., 5., 2., 0., 7.],
+            [2., 4., 3., 5., 1.]
+        ].mapv(OrderedFloat);
+        let target = array![0.48263811, 0.16705367, 0.32397016, 0.10172379, 0.54362169, 0.2];
+
+        let full = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0));
+        let mut streamed = MatrixEncoder::<_, f64, f64>::fit(
+            &data.slice(s![..2, ..]).to_owned(),
+            &target.slice(s![..2]).to_owned(),
+            &mut Encoders::new_target_encoder(1, 1.0),
+        );
+        streamed.partial_fit(&data.slice(s![2..5, ..]).to_owned(), &target.slice(s![2..5]).to_owned());
+        streamed.partial_fit(&data.slice(s![5.., ..]).to_owned(), &target.slice(s![5..]).to_owned());
+
+        let mut expected = data.clone();
+        full.transform(&mut expected);
+        let mut actual = data.clone();
+        streamed.transform(&mut actual);
+
+        Zip::from(&actual).and(&expected).for_each(|&a, &e| {
+            assert_abs_diff_eq!(a.0, e.0, epsilon = 1e-8);
+        });
+    }
+
+    #[test]
+    fn test_partial_fit_rejects_extra_targets() {
+        let data = array![[1., 2.], [1., 3.], [2., 3.]].mapv(OrderedFloat);
+        let target = array![0.2, 0.4, 0.9];
+        let mut encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0));
+        let prior = encoder.params().prior();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            encoder.partial_fit(&data.slice(s![..1, ..]).to_owned(), &array![0.1, 0.5])
+        }));
+        assert!(result.is_err());
+        assert_eq!(encoder.params().prior(), prior);
+        assert_eq!(encoder.columns()[0].stats()[&OrderedFloat(1.)].count, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "There must be a target value for each row")]
+    fn test_fit_rejects_missing_targets() {
+        let data = array![[1., 2.], [1., 3.], [2., 3.]].mapv(OrderedFloat);
+        MatrixEncoder::<_, f64, f64>::fit(&data, &array![0.2, 0.4], &mut Encoders::new_target_encoder(1, 1.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Half-life must be positive")]
+    fn test_fit_with_decay_rejects_non_positive_half_life() {
+        let data = array![[1., 2.], [1., 3.]].mapv(OrderedFloat);
+        MatrixEncoder::<_, f64, f64>::fit_with_decay(&data, &array![0.2, 0.4], &array![0., 1.], 0.0, &mut Encoders::new_target_encoder(1, 1.0));
+    }
+
+    #[test]
+    fn test_james_stein_with_decay_below_unit_weight() {
+        let data = array![[1.], [1.], [2.], [2.], [3.], [3.]].mapv(OrderedFloat);
+        let target = array![0.1, 0.3, 0.6, 0.9, 0.2, 0.8];
+        let timestamps = array![0., 0., 0., 0., 100., 100.];
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_with_decay(&data, &target, &timestamps, 1.0, &mut Encoders::new_james_stein_encoder());
+        let prior = encoder.params().prior().unwrap();
+
+        let encoded = encoder.transform_to(data.view());
+        assert_abs_diff_eq!(encoded[[0, 0]], prior, epsilon = 1e-12);
+        assert_abs_diff_eq!(encoded[[2, 0]], prior, epsilon = 1e-12);
+        assert!(encoded[[4, 0]] >= 0.2 && encoded[[4, 0]] <= 0.8);
+    }
+
+    #[test]
+    fn test_partial_fit_with_decay_matches_fit_with_decay() {
+        let data = array![
+            [2., 6., 3.],
+            [3., 2., 2.],
+            [2., 4., 5.],
+            [3., 0., 2.],
+            [2., 6., 2.],
+            [3., 4., 3.]
+        ].mapv(OrderedFloat);
+        let target = array![0.48263811, 0.16705367, 0.32397016, 0.10172379, 0.54362169, 0.2];
+        let timestamps = array![0., 1., 2., 10., 11., 12.];
+
+        let full = MatrixEncoder::<_, f64, f64>::fit_with_decay(&data, &target, &timestamps, 5.0, &mut Encoders::new_target_encoder(1, 1.0));
+        let mut streamed = MatrixEncoder::<_, f64, f64>::fit_with_decay(
+            &data.slice(s![..3, ..]).to_owned(),
+            &target.slice(s![..3]).to_owned(),
+            &timestamps.slice(s![..3]).to_owned(),
+            5.0,
+            &mut Encoders::new_target_encoder(1, 1.0),
+        );
+        streamed.partial_fit_with_decay(
+            &data.slice(s![3.., ..]).to_owned(),
+            &target.slice(s![3..]).to_owned(),
+            &timestamps.slice(s![3..]).to_owned(),
+        );
+        // an empty batch neither decays the statistics nor makes the prior NaN
+        streamed.partial_fit_with_decay(&Array2::from_elem((0, 3), OrderedFloat(0.)), &array![], &array![]);
+
+        let weights = timestamps.mapv(|t: f64| 0.5f64.powf((12. - t) / 5.0));
+        let expected_prior = (&weights * &target).sum() / weights.sum();
+        match streamed.params() {
+            Encoders::TargetEncoder { prior, .. } => assert_abs_diff_eq!(prior.unwrap(), expected_prior, epsilon = 1e-8),
+            _ => unreachable!(),
         }
+
+        let mut expected = data.clone();
+        full.transform(&mut expected);
+        let mut actual = data.clone();
+        streamed.transform(&mut actual);
+
+        Zip::from(&actual).and(&expected).for_each(|&a, &e| {
+            assert_abs_diff_eq!(a.0, e.0, epsilon = 1e-8);
+        });
+    }
+
+    #[test]
+    fn test_fit_transform_ordered() {
+        let data = array![[1], [2], [1], [1], [2], [1]];
+        let target = array![1., 3., 2., 4., 5., 6.];
+        let order = array![0., 0., 1., 2., 2., 3.];
+
+        let (encoder, encoded) = MatrixEncoder::<_, f64, f64>::fit_transform_ordered(&data, &target, &order, None, &mut Encoders::new_target_encoder(1, 1.0));
+
+        // the first block has no history and sees no targets
+        assert!(encoded[[0, 0]].is_nan());
+        assert!(encoded[[1, 0]].is_nan());
+        let (_, with_prior) = MatrixEncoder::<_, f64, f64>::fit_transform_ordered(&data, &target, &order, Some(0.5), &mut Encoders::new_target_encoder(1, 1.0));
+        assert_eq!(with_prior.slice(s![..2, 0]), array![0.5, 0.5]);
+        assert_eq!(with_prior.slice(s![2.., ..]), encoded.slice(s![2.., ..]));
+        // only one earlier row with category 1, singletons are encoded with the prior of the earlier rows
+        assert_abs_diff_eq!(encoded[[2, 0]], 2.0, epsilon = 1e-8);
+        // rows 3 and 4 share a timestamp and see rows 0..3 only
+        let smoove = 1. / (1. + (-(2. - 1.) / 1.0f64).exp());
+        assert_abs_diff_eq!(encoded[[3, 0]], 2. * (1. - smoove) + 1.5 * smoove, epsilon = 1e-8);
+        assert_abs_diff_eq!(encoded[[4, 0]], 2.0, epsilon = 1e-8);
+
+        // the fitted encoder uses all rows
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0)).transform_to(data.view());
+        assert_eq!(encoder.transform_to(data.view()), expected);
+    }
+
+    #[test]
+    fn test_fit_transform_oof() {
+        let data = array![[1], [1], [2], [2], [1], [2]];
+        let target = array![1., 2., 3., 4., 5., 6.];
+        let groups = array![10, 10, 20, 20, 30, 30];
+        let folds = FoldSplitter::new(3, 0).group_kfold(&groups);
+
+        let (_, encoded) = MatrixEncoder::<_, f64, f64>::fit_transform_oof(&data, &target, &folds, &mut Encoders::new_target_encoder(1, 1.0));
+
+        // each row is encoded with an encoder that never saw its group
+        for i in 0..data.nrows() {
+            let rest = (0..6).filter(|&j| folds[j] != folds[i]).collect_vec();
+            let expected = MatrixEncoder::<_, f64, f64>::fit(
+                &data.select(Axis(0), &rest),
+                &target.select(Axis(0), &rest),
+                &mut Encoders::new_target_encoder(1, 1.0),
+            ).transform_to(data.slice(s![i..i + 1, ..]));
+            assert_abs_diff_eq!(encoded[[i, 0]], expected[[0, 0]], epsilon = 1e-8);
+        }
+    }
+
+    #[test]
+    fn test_generic_keys() {
+        let target = array![1., 2., 2., 1., 0., 1., 2.];
+        let floats = array![[0.], [1.], [1.], [0.], [3.], [0.], [1.]].mapv(OrderedFloat);
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&floats, &target, &mut Encoders::new_target_encoder(1, 1.0))
+            .transform_to(floats.view());
+
+        // 64-bit ids that are not representable as f64
+        let ids = floats.mapv(|x| (1u64 << 60) + x.0 as u64);
+        let encoded = MatrixEncoder::<_, f64, f64>::fit(&ids, &target, &mut Encoders::new_target_encoder(1, 1.0))
+            .transform_to(ids.view());
+        assert_eq!(encoded, expected);
+
+        let names = floats.mapv(|x| ["zero", "one", "two", "three"][x.0 as usize].to_string());
+        let encoded = MatrixEncoder::<_, f64, f64>::fit(&names, &target, &mut Encoders::new_target_encoder(1, 1.0))
+            .transform_to(names.view());
+        assert_eq!(encoded, expected);
+
+        let names = names.map(|x| x.as_str());
+        let encoded = MatrixEncoder::<_, f64, f64>::fit(&names, &target, &mut Encoders::new_target_encoder(1, 1.0))
+            .transform_to(names.view());
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn test_fit_keys_with_public_bounds_only() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        struct Sku(&'static str, u32);
+
+        let data = array![[Sku("a", 1)], [Sku("a", 1)], [Sku("b", 2)]];
+        let target = array![1., 0., 1.];
+        let encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0));
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&data.mapv(|sku| sku.1), &target, &mut Encoders::new_target_encoder(1, 1.0));
+        assert_eq!(encoder.columns()[0].encodings()[&Sku("a", 1)], expected.columns()[0].encodings()[&1]);
+    }
+
+    #[test]
+    fn test_transform_unseen_category_gets_prior() {
+        let data = array![[1.], [1.], [2.]].mapv(OrderedFloat);
+        let target = array![1., 2., 6.];
+        let encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0));
+
+        let mut unseen = array![[3.]].mapv(OrderedFloat);
+        encoder.transform(&mut unseen);
+        assert_abs_diff_eq!(unseen[[0, 0]].0, 3.0, epsilon = 1e-8);
+    }
+
+    #[test]
+    fn test_column_transform_unseen_category_gets_prior() {
+        let encoder = ColumnTargetEncoder::<_, f64, f64>::fit(&array![1., 1., 2.].mapv(OrderedFloat), &array![1., 2., 6.], &Encoders::new_target_encoder(1, 1.0).with_prior(3.0));
+
+        let mut unseen = array![1., 5., -1.].mapv(OrderedFloat);
+        encoder.transform(&mut unseen);
+        assert_eq!(unseen[1], OrderedFloat(3.0));
+        assert_eq!(unseen[2], OrderedFloat(3.0));
+        assert_eq!(unseen[0].0, encoder.encode(&OrderedFloat(1.)));
+
+        let mut column = array![5., 2.].mapv(OrderedFloat);
+        encoder.transform_arr(&mut column.view_mut());
+        assert_eq!(column[0], OrderedFloat(3.0));
+    }
+
+    #[test]
+    fn test_dense_codes_match_hash_lookup() {
+        let data = array![[0_i64, 5], [3, 5], [0, 1_000_000], [2, 1_000_000], [3, 5], [0, 7]];
+        let target = array![0.3, 0.8, 0.1, 0.9, 0.4, 0.6];
+
+        let encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0));
+        assert!(encoder.columns()[0].is_dense());
+        // the largest code is much larger than the number of categories
+        assert!(!encoder.columns()[1].is_dense());
+
+        let forced = encoder.with_params(encoder.params()).with_dense_mode(DenseMode::Always);
+        assert!(forced.columns()[1].is_dense());
+        let hashed = encoder.with_params(encoder.params()).with_dense_mode(DenseMode::Never);
+        assert!(!hashed.columns()[0].is_dense());
+
+        // unseen codes inside and outside of the dense table and negative keys get the prior
+        let test = array![[0_i64, 5], [1, 6], [4, 1_000_000], [-1, 2_000_000]];
+        let expected = hashed.transform_to(test.view());
+        assert_eq!(encoder.transform_to(test.view()), expected);
+        assert_eq!(forced.transform_to(test.view()), expected);
+        assert_eq!(expected[[1, 0]], encoder.params().prior().unwrap());
+        assert_eq!(expected[[3, 0]], encoder.params().prior().unwrap());
+
+        // a dense table of a huge code would not fit in memory
+        let huge = array![[1_i64 << 40], [3]];
+        let forced = MatrixEncoder::<_, f64, f64>::fit(&huge, &array![0.3, 0.8], &mut Encoders::new_target_encoder(1, 1.0))
+            .with_dense_mode(DenseMode::Always);
+        assert!(!forced.columns()[0].is_dense());
+        assert_eq!(forced.transform_to(huge.view()), forced.with_params(forced.params()).with_dense_mode(DenseMode::Never).transform_to(huge.view()));
+    }
+
+    #[test]
+    fn test_hash_aggregation_matches_sort() {
+        let data: Array1<i64> = (0..200_000).map(|i| (i * 7919) % 13).collect();
+        let target: Array1<f64> = (0..200_000).map(|i| ((i * 31) % 17) as f64 / 17.).collect();
+        let weights: Array1<f64> = (0..200_000).map(|i| 1. + (i % 3) as f64).collect();
+
+        let hashed = ColumnTargetEncoder::<_, f64, f64>::collect_stats_hashed(data.view(), &target, Some(&weights));
+        let sorted = ColumnTargetEncoder::<_, f64, f64>::collect_stats_sorted(data.view(), &target, Some(&weights));
+        assert_eq!(hashed.len(), 13);
+        for (k, expected) in sorted.iter() {
+            let actual = &hashed[k];
+            assert_eq!(actual.count, expected.count);
+            assert_abs_diff_eq!(actual.weight, expected.weight, epsilon = 1e-6);
+            assert_abs_diff_eq!(actual.mean, expected.mean, epsilon = 1e-9);
+            assert_abs_diff_eq!(actual.m2, expected.m2, epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_transform_memory_layouts() {
+        let data: Array2<i64> = Array2::from_shape_fn((40_000, 3), |(i, j)| ((i * (j + 3)) % 11) as i64);
+        let target: Array1<f64> = (0..40_000).map(|i| ((i * 7) % 5) as f64).collect();
+        let encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0));
+
+        let expected = Array2::from_shape_fn(data.dim(), |(i, j)| encoder.columns()[j].encode(&data[[i, j]]));
+
+        let mut fortran = Array2::zeros(data.raw_dim().f());
+        fortran.assign(&data);
+        assert!(!fortran.is_standard_layout());
+        assert_eq!(encoder.transform_to(data.view()), expected);
+        let encoded = encoder.transform_to(fortran.view());
+        assert!(encoded.t().is_standard_layout());
+        assert_eq!(encoded, expected);
+
+        let float_encoder = MatrixEncoder::<_, f64, f64>::fit(&data.mapv(|k| OrderedFloat(k as f64)), &target, &mut Encoders::new_target_encoder(1, 1.0));
+        let mut in_place = fortran.mapv(|k| OrderedFloat(k as f64));
+        float_encoder.transform(&mut in_place);
+        assert_eq!(in_place.mapv(|x| x.0), expected);
+    }
+
+    #[test]
+    fn test_fit_floats_matches_fit() {
+        let data = array![[1., 2.], [1., 3.], [2., 2.], [1., 2.]];
+        let target = array![0.2, 0.7, 0.4, 0.9];
+        let mut params = Encoders::new_target_encoder(1, 1.0);
+
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_floats(data.view(), &target, &mut params.clone());
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&data.mapv(OrderedFloat), &target, &mut params)
+            .transform_to(data.mapv(OrderedFloat).view());
+
+        let mut out = Array2::zeros((4, 2));
+        encoder.transform_floats_into(data.view(), out.view_mut());
+        assert_eq!(out, expected);
+        // views with non-unit strides are read in place as well
+        let mut every_other = Array2::zeros((2, 2));
+        encoder.transform_floats_into(data.slice(s![..;2, ..]), every_other.view_mut());
+        assert_eq!(every_other, expected.slice(s![..;2, ..]));
+    }
+
+    #[test]
+    fn test_transform_to_output_type() {
+        let data = array![[1_i64, 7], [1, 3], [2, 7], [1, 7]];
+        let target = array![0.2_f32, 0.7, 0.4, 0.9];
+        let params = Encoders::new_target_encoder(1, 1.0);
+
+        let encoder = MatrixEncoder::<_, f64, f32>::fit(&data, &target, &mut params.clone());
+        let encoded: Array2<f32> = encoder.transform_to(data.view());
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&data, &target.mapv(f64::from), &mut params.clone())
+            .transform_to(data.view());
+
+        assert_eq!(encoded, expected.mapv(|e| e as f32));
+        // unseen categories get the prior in the output type as well
+        assert_abs_diff_eq!(encoder.columns()[0].encode(&5), 0.55_f32, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_fit_columns_passthrough_and_drop() {
+        let data = array![[1., 10., 0.5], [2., 10., 1.5], [1., 20., 2.5], [2., 20., 3.5]].mapv(OrderedFloat);
+        let target = array![0.2, 0.7, 0.4, 0.9];
+        let params = Encoders::new_target_encoder(1, 1.0);
+
+        let encoder = MatrixEncoder::<_, f64, f64>::fit_columns(&data, &target, &[1, 0], &mut params.clone());
+        let expected = MatrixEncoder::<_, f64, f64>::fit(&data.slice(s![.., ..2]), &target, &mut params.clone())
+            .transform_to(data.slice(s![.., ..2]));
+
+        let passed = encoder.transform_to(data.view());
+        assert_eq!(passed.slice(s![.., ..2]), expected);
+        assert_eq!(passed.column(2), array![0.5, 1.5, 2.5, 3.5]);
+        let mut in_place = data.clone();
+        encoder.transform(&mut in_place);
+        assert_eq!(in_place.mapv(|x| x.0), passed);
+        let mut f_order = Array2::zeros(data.raw_dim().f());
+        f_order.assign(&data);
+        assert_eq!(encoder.transform_to(f_order.view()), passed);
+
+        // encoded columns come in the order of the indices passed to fit_columns
+        let dropped = encoder.with_remainder(Remainder::Drop).transform_to(f_order.view());
+        assert_eq!(dropped.column(0), expected.column(1));
+        assert_eq!(dropped.column(1), expected.column(0));
+    }
+
+    #[test]
+    fn test_column_params() {
+        let data = array![[1, 5, 8], [2, 5, 8], [1, 6, 8], [2, 5, 9], [1, 6, 9], [3, 6, 8]];
+        let target = array![0.2, 0.7, 0.4, 0.9, 0.1, 0.5];
+        let default = Encoders::new_target_encoder(1, 1.0);
+        let fit = |data: &Array2<i32>, target: &Array1<f64>| {
+            MatrixEncoder::<_, f64, f64>::fit(data, target, &mut default.clone())
+                .with_column_params(1, &Encoders::new_james_stein_encoder())
+                .with_column_params(2, &Encoders::new_count_encoder())
+        };
+
+        let encoder = fit(&data, &target);
+        let encoded = encoder.transform_to(data.view());
+        let target_encoded = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut default.clone()).transform_to(data.view());
+        let james_stein = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_james_stein_encoder()).transform_to(data.view());
+        assert_eq!(encoded.column(0), target_encoded.column(0));
+        assert_eq!(encoded.column(1), james_stein.column(1));
+        assert_eq!(encoded.column(2), array![4., 4., 4., 2., 2., 4.]);
+        assert_eq!(encoder.columns()[2].encode(&10), 0.);
+
+        // column parameters are kept by partial_fit and merge
+        let mut partial = fit(&data.slice(s![..4, ..]).to_owned(), &target.slice(s![..4]).to_owned());
+        partial.partial_fit(&data.slice(s![4.., ..]), &target.slice(s![4..]).to_owned());
+        let merged = fit(&data.slice(s![..3, ..]).to_owned(), &target.slice(s![..3]).to_owned())
+            .merge(fit(&data.slice(s![3.., ..]).to_owned(), &target.slice(s![3..]).to_owned()));
+        for updated in [partial, merged].iter() {
+            for (a, b) in updated.transform_to(data.view()).iter().zip(encoded.iter()) {
+                assert_abs_diff_eq!(a, b, epsilon = 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rare_grouping() {
+        let data = array!["a", "a", "a", "b", "b", "c", "d"];
+        let target = array![0.2, 0.4, 0.3, 0.9, 0.7, 0.1, 0.6];
+        let mut params = Encoders::new_target_encoder(1, 1.0);
+        params.compute_global_stats(&target);
+        let rare = RareGrouping { min_frequency: 2, max_categories: None };
+
+        let encoder = ColumnTargetEncoder::<_, f64, f64>::fit(&data, &target, &params).with_rare_grouping(rare);
+        let rare_stats: TargetStats<f64> = vec![0.1, 0.6].into_iter().collect();
+        assert_eq!(encoder.encodings().len(), 2);
+        assert_abs_diff_eq!(encoder.rare_stats().mean, rare_stats.mean, epsilon = 1e-12);
+        let other = compute_encoding(&params, encoder.rare_stats(), 3).unwrap();
+        assert_eq!(encoder.encode(&"c"), other);
+        assert_eq!(encoder.encode(&"d"), other);
+        assert_eq!(encoder.encode(&"unseen"), other);
+        assert_ne!(other, params.prior().unwrap());
+
+        let top = ColumnTargetEncoder::<_, f64, f64>::fit(&data, &target, &params)
+            .with_rare_grouping(RareGrouping { min_frequency: 1, max_categories: Some(1) });
+        assert_eq!(top.encodings().keys().collect_vec(), vec![&"a"]);
+        assert_eq!(top.rare_stats().count, 4);
+
+        // categories that stay rare after partial_fit are folded as well
+        let mut partial = ColumnTargetEncoder::<_, f64, f64>::fit(&data, &target, &params).with_rare_grouping(rare);
+        partial.partial_fit(&array!["e", "c", "b"], &array![0.5, 0.5, 0.5], &params);
+        assert_eq!(partial.encodings().len(), 2);
+        assert_eq!(partial.rare_stats().count, 4);
+    }
+
+    #[test]
+    fn test_compact() {
+        // column 0 has 50 ids seen once and one frequent id, column 1 a single category encoded with the prior
+        let mut data = Array2::from_elem((55, 2), 7);
+        let mut target = Array1::zeros(55);
+        for i in 0..55 {
+            data[[i, 0]] = if i < 50 { i as i64 } else { 100 };
+            target[i] = if i < 50 { i as f64 / 50. } else { 2. };
+        }
+        let mut encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0))
+            .with_dense_mode(DenseMode::Never);
+        let encoded = encoder.transform_to(data.view());
+        let frequent = encoder.columns()[0].encode(&100);
+
+        let report = encoder.compact(2, 1e-9);
+        assert_eq!((report.categories_before, report.categories_after), (52, 1));
+        assert!(report.bytes_after < report.bytes_before);
+        assert_eq!(encoder.memory_size(), report.bytes_after);
+        assert_eq!(encoder.columns()[0].encodings().keys().collect_vec(), vec![&100]);
+        assert_eq!(encoder.columns()[0].encode(&100), frequent);
+        assert!(encoder.columns()[1].encodings().is_empty());
+        for (a, b) in encoder.transform_to(data.view()).iter().zip(encoded.iter()) {
+            assert_abs_diff_eq!(a, b, epsilon = 1e-9);
+        }
+
+        let downcast = encoder.with_output_type::<f32>();
+        assert_eq!(downcast.columns()[0].encode(&100), frequent as f32);
+        assert_eq!(downcast.transform_to(data.view()), encoded.mapv(|e| e as f32));
+    }
+
+    #[test]
+    fn test_check_column_names() {
+        let data = array![[1, 2], [2, 2]];
+        let target = array![1., 2.];
+        let encoder = MatrixEncoder::<_, f64, f64>::fit(&data, &target, &mut Encoders::new_target_encoder(1, 1.0))
+            .with_column_names(vec!["city".to_string(), "brand".to_string()]);
+
+        assert!(encoder.check_column_names(&["city".to_string(), "brand".to_string()]).is_ok());
+        assert!(encoder.check_column_names(&["brand".to_string(), "city".to_string()]).is_err());
+        assert!(encoder.with_params(&Encoders::new_auto_target_encoder()).check_column_names(&["city".to_string()]).is_err());
     }
 
     #[test]
@@ -1,5 +1,5 @@
 use itertools::Itertools;
-use ndarray::{Array2, Array1};
+use ndarray::{Array2, Array1, ArrayView, Dimension};
 use num_traits::{AsPrimitive, Float, Num};
 use ordered_float::OrderedFloat;
 use rand::prelude::Distribution;
@@ -42,6 +42,12 @@ impl ToOrderedFloat<f64> for Array1<f64> {
     }
 }
 
+/// View an array of floats as an array of [`OrderedFloat`] without copying
+pub fn as_ordered_float<'a, T: Float, D: Dimension>(view: ArrayView<'a, T, D>) -> ArrayView<'a, OrderedFloat<T>, D> {
+    // `OrderedFloat` is `repr(transparent)`, so both arrays have the same memory layout
+    unsafe { view.raw_view().cast::<OrderedFloat<T>>().deref_into_view() }
+}
+
 pub fn gen_array<T, D>(n: usize, distr: &D) -> Vec<T>
     where
         T: Num + Clone,